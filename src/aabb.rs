@@ -1,6 +1,7 @@
 use std::ops::Add;
 
 use crate::{
+    common::FP,
     interval::Interval,
     ray::Ray,
     vec3::{Point3, Vec3},
@@ -14,6 +15,12 @@ pub struct AABB {
 }
 
 impl AABB {
+    pub const EMPTY: Self = Self {
+        x: Interval::EMPTY,
+        y: Interval::EMPTY,
+        z: Interval::EMPTY,
+    };
+
     pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
         Self { x, y, z }
     }
@@ -52,6 +59,19 @@ impl AABB {
         self
     }
 
+    pub fn surface_area(&self) -> FP {
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            (self.x.min + self.x.max) * 0.5,
+            (self.y.min + self.y.max) * 0.5,
+            (self.z.min + self.z.max) * 0.5,
+        )
+    }
+
     pub fn axis(&self, n: usize) -> &Interval {
         assert!(n <= 2);
         match n {
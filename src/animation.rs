@@ -0,0 +1,302 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Add, Mul, Range, Sub},
+};
+
+use serde::{
+    de::{self, value::MapAccessDeserializer, value::SeqAccessDeserializer, MapAccess, SeqAccess},
+    de::{IntoDeserializer, Visitor},
+    Deserialize, Deserializer,
+};
+
+use crate::{
+    camera::View,
+    common::FP,
+    vec3::{Point3, Vec3},
+};
+
+/// Values that can be blended between keyframes.
+pub trait Interpolate:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<FP, Output = Self>
+{
+}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<FP, Output = T>> Interpolate for T {}
+
+/// How a track moves from one keyframe to the next.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Smooth curve through the keyframes, easing in and out of the first and last one
+    Bezier,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe<T> {
+    /// Seconds from the start of the animation
+    pub time: FP,
+    pub value: T,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TrackDesc<T> {
+    keyframes: Vec<Keyframe<T>>,
+    #[serde(default)]
+    interpolation: Interpolation,
+}
+
+/// A value over time, held before the first and after the last keyframe.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "TrackDesc<T>", bound = "T: Deserialize<'de>")]
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>,
+    interpolation: Interpolation,
+}
+
+impl<T> TryFrom<TrackDesc<T>> for Track<T> {
+    type Error = String;
+
+    fn try_from(desc: TrackDesc<T>) -> Result<Self, Self::Error> {
+        if desc.keyframes.is_empty() {
+            return Err("a track needs at least one keyframe".to_owned());
+        }
+        if desc.keyframes.windows(2).any(|k| k[0].time >= k[1].time) {
+            return Err("keyframe times have to increase".to_owned());
+        }
+        Ok(Self {
+            keyframes: desc.keyframes,
+            interpolation: desc.interpolation,
+        })
+    }
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn value_at(&self, time: FP) -> T {
+        let keys = &self.keyframes;
+        let next = keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return keys[0].value;
+        }
+        if next == keys.len() {
+            return keys[next - 1].value;
+        }
+
+        let (k0, k1) = (&keys[next - 1], &keys[next]);
+        let dt = k1.time - k0.time;
+        let s = (time - k0.time) / dt;
+        match self.interpolation {
+            Interpolation::Linear => k0.value + (k1.value - k0.value) * s,
+            Interpolation::Bezier => {
+                // Control points from Catmull-Rom tangents, flat at both ends
+                let c1 = k0.value + self.tangent(next - 1) * (dt / 3.0);
+                let c2 = k1.value - self.tangent(next) * (dt / 3.0);
+                let t = 1.0 - s;
+                k0.value * (t * t * t)
+                    + c1 * (3.0 * s * t * t)
+                    + c2 * (3.0 * s * s * t)
+                    + k1.value * (s * s * s)
+            }
+        }
+    }
+
+    /// Change per second at keyframe `i`.
+    fn tangent(&self, i: usize) -> T {
+        let keys = &self.keyframes;
+        if i == 0 || i + 1 == keys.len() {
+            return keys[i].value * 0.0;
+        }
+        let (prev, next) = (&keys[i - 1], &keys[i + 1]);
+        (next.value - prev.value) * (1.0 / (next.time - prev.time))
+    }
+}
+
+/// A scene file value, either given as is or as a table of keyframes.
+#[derive(Debug, Clone)]
+pub enum Animated<T> {
+    Static(T),
+    Keyframed(Track<T>),
+}
+
+impl<T> Animated<T> {
+    pub fn is_keyframed(&self) -> bool {
+        matches!(self, Animated::Keyframed(_))
+    }
+}
+
+impl<T: Interpolate> Animated<T> {
+    pub fn value_at(&self, time: FP) -> T {
+        match self {
+            Animated::Static(value) => *value,
+            Animated::Keyframed(track) => track.value_at(time),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Animated<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AnimatedVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for AnimatedVisitor<T> {
+            type Value = Animated<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a value or a table of `keyframes`")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                T::deserialize(v.into_deserializer()).map(Animated::Static)
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                T::deserialize(v.into_deserializer()).map(Animated::Static)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                T::deserialize(SeqAccessDeserializer::new(seq)).map(Animated::Static)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                Track::deserialize(MapAccessDeserializer::new(map)).map(Animated::Keyframed)
+            }
+        }
+
+        deserializer.deserialize_any(AnimatedVisitor(PhantomData))
+    }
+}
+
+/// Keyframed parts of the camera view, replacing the ones in the `[camera]` table.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraAnimation {
+    pub look_from: Option<Track<Point3>>,
+    pub look_at: Option<Track<Point3>>,
+    pub vup: Option<Track<Vec3>>,
+    pub vfov: Option<Track<FP>>,
+    pub defocus_angle: Option<Track<FP>>,
+    pub focus_dist: Option<Track<FP>>,
+}
+
+impl CameraAnimation {
+    pub fn view_at(&self, view: View, time: FP) -> View {
+        fn at<T: Interpolate>(track: &Option<Track<T>>, value: T, time: FP) -> T {
+            track.as_ref().map_or(value, |track| track.value_at(time))
+        }
+
+        View {
+            look_from: at(&self.look_from, view.look_from, time),
+            look_at: at(&self.look_at, view.look_at, time),
+            vup: at(&self.vup, view.vup, time),
+            vfov: at(&self.vfov, view.vfov, time),
+            defocus_angle: at(&self.defocus_angle, view.defocus_angle, time),
+            focus_dist: at(&self.focus_dist, view.focus_dist, time),
+        }
+    }
+}
+
+/// Parses a `start..end` range of frame numbers, the end being excluded.
+pub fn parse_frames(s: &str) -> Result<Range<u32>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| "expected a range like `0..120`".to_owned())?;
+    let start = start.trim().parse::<u32>().map_err(|err| err.to_string())?;
+    let end = end.trim().parse::<u32>().map_err(|err| err.to_string())?;
+    if start >= end {
+        return Err("the range has no frames in it".to_owned());
+    }
+    Ok(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(interpolation: Interpolation, keyframes: &[(FP, FP)]) -> Track<FP> {
+        Track::try_from(TrackDesc {
+            keyframes: keyframes
+                .iter()
+                .map(|&(time, value)| Keyframe { time, value })
+                .collect(),
+            interpolation,
+        })
+        .expect("Should've been a valid track")
+    }
+
+    fn assert_close(a: FP, b: FP) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn linear_track_hits_keyframes_and_midpoints() {
+        let track = track(Interpolation::Linear, &[(0.0, 0.0), (1.0, 1.0), (3.0, 5.0)]);
+
+        assert_close(track.value_at(0.0), 0.0);
+        assert_close(track.value_at(1.0), 1.0);
+        assert_close(track.value_at(3.0), 5.0);
+        assert_close(track.value_at(0.5), 0.5);
+        assert_close(track.value_at(2.0), 3.0);
+    }
+
+    #[test]
+    fn track_holds_outside_its_keyframes() {
+        let track = track(Interpolation::Bezier, &[(1.0, 2.0), (2.0, 4.0)]);
+
+        assert_close(track.value_at(-5.0), 2.0);
+        assert_close(track.value_at(10.0), 4.0);
+    }
+
+    #[test]
+    fn bezier_track_hits_keyframes_and_eases() {
+        let track = track(Interpolation::Bezier, &[(0.0, 0.0), (1.0, 1.0), (2.0, 4.0)]);
+
+        assert_close(track.value_at(0.0), 0.0);
+        assert_close(track.value_at(1.0), 1.0);
+        assert_close(track.value_at(2.0), 4.0);
+        // Flat at the first keyframe, heading for the last one through the middle one
+        assert_close(track.value_at(0.5), 0.25);
+        assert_close(track.value_at(1.5), 2.75);
+    }
+
+    #[test]
+    fn animated_values_parse_as_is_or_keyframed() {
+        #[derive(Deserialize)]
+        struct Values {
+            fixed: Animated<Vec3>,
+            moving: Animated<FP>,
+        }
+
+        let values: Values = toml::from_str(
+            "fixed = [1.0, 2.0, 3.0]\n\
+             moving = { keyframes = [{ time = 0.0, value = 1 }, { time = 2.0, value = 3.0 }] }",
+        )
+        .expect("Should've parsed the values");
+
+        assert!(!values.fixed.is_keyframed());
+        assert_eq!(values.fixed.value_at(1.0), Vec3::new(1.0, 2.0, 3.0));
+        assert!(values.moving.is_keyframed());
+        assert_close(values.moving.value_at(1.0), 2.0);
+    }
+
+    #[test]
+    fn keyframes_have_to_be_in_order() {
+        let desc = TrackDesc {
+            keyframes: vec![
+                Keyframe {
+                    time: 1.0,
+                    value: 0.0,
+                },
+                Keyframe {
+                    time: 1.0,
+                    value: 1.0,
+                },
+            ],
+            interpolation: Interpolation::Linear,
+        };
+
+        assert!(Track::try_from(desc).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{ops::AddAssign, path::Path};
+
+use clap::ValueEnum;
+use image::ImageResult;
+
+use crate::{
+    color::{heat_color, ToneMapping},
+    common::FP,
+    hittable::Hittable,
+    interval::Interval,
+    output::{save_image, OutputFormat},
+    ray::Ray,
+    vec3::{Color, Point3, Vec3},
+};
+
+/// Arbitrary output variables, auxiliary passes written beside the beauty pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AOV {
+    /// Surface color of the first hit
+    Albedo,
+    /// Shading normal of the first hit
+    Normal,
+    /// Distance from the camera to the first hit
+    Depth,
+    /// World position of the first hit
+    Position,
+    /// Object of the first hit, numbered by its place in the scene
+    Id,
+    /// Number of samples spent on each pixel, as a heatmap in PNG
+    Samples,
+}
+
+impl AOV {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AOV::Albedo => "albedo",
+            AOV::Normal => "normal",
+            AOV::Depth => "depth",
+            AOV::Position => "position",
+            AOV::Id => "id",
+            AOV::Samples => "samples",
+        }
+    }
+}
+
+/// First hit data of a camera ray, summed over the samples of a pixel.
+#[derive(Default, Clone, Copy)]
+pub struct AOVSample {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub depth: FP,
+    pub position: Point3,
+    /// Object id of the first hit, zero when the ray escaped.
+    pub id: u32,
+}
+
+impl AOVSample {
+    pub fn new(ray: &Ray, world: &dyn Hittable) -> Self {
+        match world.hit(ray, &Interval::new(0.001, FP::INFINITY)) {
+            Some(hit) => Self {
+                albedo: hit.mat.albedo(&hit),
+                normal: hit.normal,
+                depth: hit.t * ray.direction.length(),
+                position: hit.p,
+                id: hit.object_id,
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+impl AddAssign for AOVSample {
+    /// Sums the continuous passes, while the object id of the first sample is kept.
+    fn add_assign(&mut self, rhs: Self) {
+        self.albedo += rhs.albedo;
+        self.normal += rhs.normal;
+        self.depth += rhs.depth;
+        self.position += rhs.position;
+        if self.id == 0 {
+            self.id = rhs.id;
+        }
+    }
+}
+
+/// Writes every requested pass next to `path`, e.g. `output.albedo.exr` for `output.exr`.
+/// `samples` holds the sums over the `sample_counts` samples of each pixel. Float formats
+/// store the raw values while PNG gets a visualization of them.
+pub fn save_aovs(
+    aovs: &[AOV],
+    samples: &[AOVSample],
+    sample_counts: &[u32],
+    path: &Path,
+    format: OutputFormat,
+    width: usize,
+    height: usize,
+) -> ImageResult<()> {
+    let visualize = format == OutputFormat::Png;
+    let samples = samples
+        .iter()
+        .zip(sample_counts)
+        .map(|(s, &count)| {
+            let count = count.max(1) as FP;
+            AOVSample {
+                albedo: s.albedo / count,
+                normal: s.normal / count,
+                depth: s.depth / count,
+                position: s.position / count,
+                id: s.id,
+            }
+        })
+        .collect::<Vec<AOVSample>>();
+
+    for aov in aovs {
+        let pixels: Vec<Color> = match aov {
+            AOV::Albedo => samples.iter().map(|s| s.albedo).collect(),
+            AOV::Normal => samples
+                .iter()
+                .map(|s| {
+                    if visualize {
+                        0.5 * (s.normal + Vec3::ONE)
+                    } else {
+                        s.normal
+                    }
+                })
+                .collect(),
+            AOV::Depth => {
+                let max_depth = samples.iter().map(|s| s.depth).fold(0.0, FP::max);
+                samples
+                    .iter()
+                    .map(|s| {
+                        if visualize && max_depth > 0.0 {
+                            Color::splat(s.depth / max_depth)
+                        } else {
+                            Color::splat(s.depth)
+                        }
+                    })
+                    .collect()
+            }
+            AOV::Position => samples.iter().map(|s| s.position).collect(),
+            AOV::Id => samples
+                .iter()
+                .map(|s| {
+                    if visualize {
+                        id_to_color(s.id)
+                    } else {
+                        Color::splat(s.id as FP)
+                    }
+                })
+                .collect(),
+            AOV::Samples => {
+                let max_count = sample_counts.iter().copied().max().unwrap_or(1).max(1);
+                sample_counts
+                    .iter()
+                    .map(|&count| {
+                        if visualize {
+                            heat_color(count as FP / max_count as FP)
+                        } else {
+                            Color::splat(count as FP)
+                        }
+                    })
+                    .collect()
+            }
+        };
+
+        let extension = format!("{}.{}", aov.name(), format.extension());
+        save_image(
+            &path.with_extension(extension),
+            format,
+            &ToneMapping::default(),
+            &pixels,
+            width,
+            height,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Spreads ids around the hue circle so neighbouring ids get distinct colors.
+fn id_to_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::ZERO;
+    }
+    let hue = (id as FP * 0.618_033_988_749_895).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    match hue as usize {
+        0 => Color::new(1.0, x, 0.0),
+        1 => Color::new(x, 1.0, 0.0),
+        2 => Color::new(0.0, 1.0, x),
+        3 => Color::new(0.0, x, 1.0),
+        4 => Color::new(x, 0.0, 1.0),
+        _ => Color::new(1.0, 0.0, x),
+    }
+}
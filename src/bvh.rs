@@ -1,21 +1,83 @@
-use std::{cmp::Ordering, sync::Arc};
+use std::{cell::Cell, cmp::Ordering, fmt, sync::Arc};
 
-use rand::Rng;
+use clap::ValueEnum;
+use serde::Deserialize;
 
 use crate::{
     aabb::AABB,
-    hittable::{HitRecord, Hittable, HittableList},
+    common::{self, FP},
+    hittable::{self, HitRecord, Hittable, HittableList},
     interval::Interval,
     ray::Ray,
 };
 
+const SAH_BINS: usize = 16;
+const SAH_MAX_LEAF_SIZE: usize = 8;
+/// Beyond this depth the SAH builder falls back to median splits, keeping the tree shallow
+/// enough for the fixed size traversal stack.
+const SAH_MAX_DEPTH: usize = 32;
+const STACK_SIZE: usize = 64;
+const TRAVERSAL_COST: FP = 0.125;
+const INTERSECTION_COST: FP = 1.0;
+
+thread_local! {
+    /// Bounding box and object tests done by [`BVHNode::hit`] on this thread.
+    static TRAVERSAL_STEPS: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Returns the traversal steps taken on this thread since the last call.
+pub fn take_traversal_steps() -> u32 {
+    TRAVERSAL_STEPS.with(Cell::take)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BVHBuilder {
+    /// Splits at the object median along a random axis
+    Median,
+    /// Binned surface area heuristic
+    #[default]
+    Sah,
+}
+
 pub struct BVHNode {
-    root: (Node, AABB),
+    nodes: Vec<LinearNode>,
+    objects: Vec<Arc<dyn Hittable>>,
+}
+
+/// A node of the flattened tree. The first child of a branch directly follows it in
+/// `nodes` while `offset` points to the second one. For leaves `offset` is the index of
+/// their first object and `count` the number of objects, which is zero for an empty tree.
+struct LinearNode {
+    bbox: AABB,
+    offset: u32,
+    count: u16,
+    axis: u8,
+    is_leaf: bool,
+}
+
+enum BuildNode {
+    Branch(Box<(BuildNode, AABB)>, Box<(BuildNode, AABB)>, usize),
+    Leaf(Vec<Arc<dyn Hittable>>),
 }
 
-enum Node {
-    Branch(Box<(Node, AABB)>, Box<(Node, AABB)>),
-    Leaf(Arc<dyn Hittable>),
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BVHStats {
+    pub depth: usize,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_leaf_size: usize,
+    pub sah_cost: FP,
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "depth: {}, nodes: {}, leaves: {}, max leaf size: {}, SAH cost: {:.2}",
+            self.depth, self.node_count, self.leaf_count, self.max_leaf_size, self.sah_cost
+        )
+    }
 }
 
 impl BVHNode {
@@ -23,13 +85,91 @@ impl BVHNode {
         Self::new_from_objects(&mut list.objects)
     }
     pub fn new_from_objects(objects: &mut [Arc<dyn Hittable>]) -> Self {
-        Self {
-            root: Self::node_from_list(objects),
+        Self::new_from_objects_with_builder(objects, BVHBuilder::default())
+    }
+    pub fn new_with_builder(list: &mut HittableList, builder: BVHBuilder) -> Self {
+        Self::new_from_objects_with_builder(&mut list.objects, builder)
+    }
+    pub fn new_from_objects_with_builder(
+        objects: &mut [Arc<dyn Hittable>],
+        builder: BVHBuilder,
+    ) -> Self {
+        let root = match builder {
+            BVHBuilder::Median => Self::node_from_list(objects),
+            BVHBuilder::Sah => Self::sah_node_from_list(objects, 1),
+        };
+
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            objects: Vec::with_capacity(objects.len()),
+        };
+        bvh.flatten(root);
+        assert!(
+            bvh.stats().depth <= STACK_SIZE,
+            "BVH is too deep for the traversal stack"
+        );
+        bvh
+    }
+
+    fn flatten(&mut self, (node, bbox): (BuildNode, AABB)) -> usize {
+        let index = self.nodes.len();
+        match node {
+            BuildNode::Branch(left, right, axis) => {
+                self.nodes.push(LinearNode {
+                    bbox,
+                    offset: 0,
+                    count: 0,
+                    axis: axis as u8,
+                    is_leaf: false,
+                });
+                self.flatten(*left);
+                self.nodes[index].offset = self.flatten(*right) as u32;
+            }
+            BuildNode::Leaf(objects) => {
+                self.nodes.push(LinearNode {
+                    bbox,
+                    offset: self.objects.len() as u32,
+                    count: objects.len() as u16,
+                    axis: 0,
+                    is_leaf: true,
+                });
+                self.objects.extend(objects);
+            }
+        }
+        index
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats::default();
+        let root_area = self.nodes[0].bbox.surface_area();
+        self.collect_stats(0, 1, root_area, &mut stats);
+        stats
+    }
+
+    fn collect_stats(&self, index: usize, depth: usize, root_area: FP, stats: &mut BVHStats) {
+        let node = &self.nodes[index];
+        let area_ratio = if root_area > 0.0 && root_area.is_finite() {
+            node.bbox.surface_area() / root_area
+        } else {
+            1.0
+        };
+
+        stats.depth = stats.depth.max(depth);
+        stats.node_count += 1;
+
+        if !node.is_leaf {
+            stats.sah_cost += area_ratio * TRAVERSAL_COST;
+            self.collect_stats(index + 1, depth + 1, root_area, stats);
+            self.collect_stats(node.offset as usize, depth + 1, root_area, stats);
+        } else {
+            stats.leaf_count += 1;
+            stats.max_leaf_size = stats.max_leaf_size.max(node.count as usize);
+            stats.sah_cost += area_ratio * INTERSECTION_COST * node.count as FP;
         }
     }
 
-    fn node_from_list(objects: &mut [Arc<dyn Hittable>]) -> (Node, AABB) {
-        let axis: usize = rand::thread_rng().gen_range(0..=2);
+    fn node_from_list(objects: &mut [Arc<dyn Hittable>]) -> (BuildNode, AABB) {
+        let axis: usize = common::random_range(0..=2);
 
         let comparator = match axis {
             0 => Self::box_x_compare,
@@ -39,9 +179,11 @@ impl BVHNode {
 
         let object_span = objects.len();
 
-        if object_span == 1 {
+        if object_span == 0 {
+            (BuildNode::Leaf(Vec::new()), AABB::EMPTY)
+        } else if object_span == 1 {
             let obj = &objects[0];
-            (Node::Leaf(Arc::clone(&obj)), obj.bounding_box())
+            (BuildNode::Leaf(vec![Arc::clone(obj)]), obj.bounding_box())
         } else if object_span == 2 {
             let mut left = &objects[0];
             let mut right = &objects[1];
@@ -49,9 +191,13 @@ impl BVHNode {
                 (left, right) = (right, left);
             }
             (
-                Node::Branch(
-                    Box::new((Node::Leaf(Arc::clone(left)), left.bounding_box())),
-                    Box::new((Node::Leaf(Arc::clone(right)), right.bounding_box())),
+                BuildNode::Branch(
+                    Box::new((BuildNode::Leaf(vec![Arc::clone(left)]), left.bounding_box())),
+                    Box::new((
+                        BuildNode::Leaf(vec![Arc::clone(right)]),
+                        right.bounding_box(),
+                    )),
+                    axis,
                 ),
                 AABB::new_from_aabbs(left.bounding_box(), right.bounding_box()),
             )
@@ -61,10 +207,131 @@ impl BVHNode {
             let left = Self::node_from_list(left_objects);
             let right = Self::node_from_list(right_objects);
             let bbox = AABB::new_from_aabbs(left.1, right.1);
-            (Node::Branch(Box::new(left), Box::new(right)), bbox)
+            (
+                BuildNode::Branch(Box::new(left), Box::new(right), axis),
+                bbox,
+            )
         }
     }
 
+    fn sah_node_from_list(objects: &mut [Arc<dyn Hittable>], depth: usize) -> (BuildNode, AABB) {
+        let bbox = objects.iter().fold(AABB::EMPTY, |bbox, obj| {
+            AABB::new_from_aabbs(bbox, obj.bounding_box())
+        });
+        let centroid_bounds = objects.iter().fold(AABB::EMPTY, |bounds, obj| {
+            let c = obj.bounding_box().centroid();
+            AABB::new_from_aabbs(bounds, AABB::new_from_points(c, c))
+        });
+
+        let object_span = objects.len();
+        let leaf_cost = INTERSECTION_COST * object_span as FP;
+        let make_leaf = |objects: &[Arc<dyn Hittable>]| (BuildNode::Leaf(objects.to_vec()), bbox);
+
+        if object_span <= 1 {
+            return make_leaf(objects);
+        }
+
+        // Find the cheapest split over all axes, each binned by centroid position
+        let mut best: Option<(FP, usize, usize)> = None;
+        for axis in 0..3 {
+            let extent = *centroid_bounds.axis(axis);
+            if extent.size() <= 0.0 {
+                continue;
+            }
+
+            let mut counts = [0usize; SAH_BINS];
+            let mut bounds = [AABB::EMPTY; SAH_BINS];
+            for obj in objects.iter() {
+                let b = Self::bin_index(&obj.bounding_box(), axis, &extent);
+                counts[b] += 1;
+                bounds[b] = AABB::new_from_aabbs(bounds[b], obj.bounding_box());
+            }
+
+            // Sweep from the right to get the cost of everything above each split
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right_count = [0usize; SAH_BINS];
+            let mut acc_bounds = AABB::EMPTY;
+            let mut acc_count = 0;
+            for b in (1..SAH_BINS).rev() {
+                acc_bounds = AABB::new_from_aabbs(acc_bounds, bounds[b]);
+                acc_count += counts[b];
+                right_area[b] = if acc_count > 0 {
+                    acc_bounds.surface_area()
+                } else {
+                    0.0
+                };
+                right_count[b] = acc_count;
+            }
+
+            let mut acc_bounds = AABB::EMPTY;
+            let mut acc_count = 0;
+            for split in 1..SAH_BINS {
+                acc_bounds = AABB::new_from_aabbs(acc_bounds, bounds[split - 1]);
+                acc_count += counts[split - 1];
+                if acc_count == 0 || right_count[split] == 0 {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST
+                    + INTERSECTION_COST
+                        * (acc_count as FP * acc_bounds.surface_area()
+                            + right_count[split] as FP * right_area[split])
+                        / bbox.surface_area();
+                if !matches!(best, Some((best_cost, _, _)) if best_cost <= cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (mid, axis) = match best {
+            Some((cost, _, _)) if cost >= leaf_cost && object_span <= SAH_MAX_LEAF_SIZE => {
+                return make_leaf(objects);
+            }
+            Some((_, axis, _)) if depth >= SAH_MAX_DEPTH => {
+                let extent = *centroid_bounds.axis(axis);
+                objects.sort_unstable_by(|a, b| {
+                    Self::bin_index(&a.bounding_box(), axis, &extent).cmp(&Self::bin_index(
+                        &b.bounding_box(),
+                        axis,
+                        &extent,
+                    ))
+                });
+                (object_span / 2, axis)
+            }
+            Some((_, axis, split)) => {
+                let extent = *centroid_bounds.axis(axis);
+                objects.sort_unstable_by(|a, b| {
+                    Self::bin_index(&a.bounding_box(), axis, &extent).cmp(&Self::bin_index(
+                        &b.bounding_box(),
+                        axis,
+                        &extent,
+                    ))
+                });
+                let mid = objects
+                    .iter()
+                    .position(|obj| Self::bin_index(&obj.bounding_box(), axis, &extent) >= split)
+                    .unwrap_or(object_span / 2);
+                (mid, axis)
+            }
+            // All centroids coincide, so no split can separate them
+            None if object_span <= SAH_MAX_LEAF_SIZE => return make_leaf(objects),
+            None => (object_span / 2, 0),
+        };
+
+        let (left_objects, right_objects) = objects.split_at_mut(mid);
+        let left = Self::sah_node_from_list(left_objects, depth + 1);
+        let right = Self::sah_node_from_list(right_objects, depth + 1);
+        (
+            BuildNode::Branch(Box::new(left), Box::new(right), axis),
+            bbox,
+        )
+    }
+
+    fn bin_index(bbox: &AABB, axis: usize, extent: &Interval) -> usize {
+        let offset = (bbox.centroid()[axis] - extent.min) / extent.size();
+        ((offset * SAH_BINS as FP) as usize).min(SAH_BINS - 1)
+    }
+
     fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: usize) -> Ordering {
         if a.bounding_box().axis(axis).min < b.bounding_box().axis(axis).min {
             Ordering::Less
@@ -83,41 +350,67 @@ impl BVHNode {
     }
 }
 
-trait NodeHit {
-    fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord>;
-}
-
-impl NodeHit for (Node, AABB) {
+impl Hittable for BVHNode {
     fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord> {
-        if !self.1.hit(r, &ray_t) {
-            return None;
-        }
+        let dir_is_neg = [
+            r.direction.x < 0.0,
+            r.direction.y < 0.0,
+            r.direction.z < 0.0,
+        ];
 
-        match &self.0 {
-            Node::Branch(left, right) => {
-                if let Some(hit_left) = left.hit(r, ray_t) {
-                    if let Some(hit_right) = right.hit(r, &Interval::new(ray_t.min, hit_left.t)) {
-                        Some(hit_right)
-                    } else {
-                        Some(hit_left)
+        let mut closest_so_far = ray_t.max;
+        let mut hit_anything = None;
+
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut index = 0;
+        let mut steps = 0;
+
+        loop {
+            let node = &self.nodes[index];
+            steps += 1;
+
+            if node.bbox.hit(r, &Interval::new(ray_t.min, closest_so_far)) {
+                if node.is_leaf {
+                    let first = node.offset as usize;
+                    steps += node.count as u32;
+                    for object in &self.objects[first..first + node.count as usize] {
+                        if let Some(hit) = object.hit(r, &Interval::new(ray_t.min, closest_so_far))
+                        {
+                            closest_so_far = hit.t;
+                            hit_anything = Some(hit);
+                        }
                     }
-                } else if let Some(hit_right) = right.hit(r, ray_t) {
-                    Some(hit_right)
                 } else {
-                    None
+                    // Visit the child nearest to the ray origin first and defer the other one
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset, index as u32 + 1)
+                    } else {
+                        (index as u32 + 1, node.offset)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    index = near as usize;
+                    continue;
                 }
             }
-            Node::Leaf(obj) => obj.hit(r, ray_t),
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            index = stack[stack_len] as usize;
         }
-    }
-}
 
-impl Hittable for BVHNode {
-    fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord> {
-        self.root.hit(r, &ray_t)
+        TRAVERSAL_STEPS.with(|s| s.set(s.get() + steps));
+        hit_anything
     }
 
     fn bounding_box(&self) -> AABB {
-        self.root.1
+        self.nodes[0].bbox
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        hittable::collect_lights(&self.objects, lights);
     }
 }
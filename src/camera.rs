@@ -1,22 +1,58 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::animation::CameraAnimation;
+use crate::color::{ToneMapper, ToneMapping};
 use crate::common::{degrees_to_radians, FP};
+use crate::environment::{ConstantEnvironment, Environment};
 use crate::vec3::Color;
 use crate::{
     ray::Ray,
+    sampler::Sampler,
     vec3::{Point3, Vec3},
 };
 
+/// How far the shutter is open over the exposure, weighting the times rays are sent at.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutterCurve {
+    /// Fully open the whole time
+    #[default]
+    Box,
+    /// Opening up to the middle of the exposure and closing again
+    Triangle,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct CameraSettings {
     pub aspect_ratio: FP,
     pub image_width: usize,
     pub samples_per_pixel: i32,
     pub max_depth: i32,
+    /// Bounces before paths may get terminated by Russian roulette
+    pub roulette_depth: i32,
     pub vfov: FP,
     pub look_from: Point3,
     pub look_at: Point3,
+    /// Where the camera moves to by the time the shutter closes in still images, sequences
+    /// move it by `animation` instead
+    pub end_look_from: Option<Point3>,
+    pub end_look_at: Option<Point3>,
     pub vup: Vec3,
     pub defocus_angle: FP,
     pub focus_dist: FP,
+    /// Time the shutter opens at, objects move from time 0 to 1
+    pub shutter_open: FP,
+    pub shutter_close: FP,
+    pub shutter_curve: ShutterCurve,
     pub background: Color,
+    pub exposure: FP,
+    pub tone_mapper: ToneMapper,
+    pub white_point: FP,
+    /// Keyframes of the view for image sequences
+    pub animation: CameraAnimation,
 }
 impl Default for CameraSettings {
     fn default() -> Self {
@@ -25,31 +61,80 @@ impl Default for CameraSettings {
             image_width: 400,
             samples_per_pixel: 100,
             max_depth: 50,
+            roulette_depth: 3,
             vfov: 90.0,
             look_from: Point3::ZERO,
             look_at: Point3::new(0.0, 0.0, -1.0),
+            end_look_from: None,
+            end_look_at: None,
             vup: Vec3::UP,
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            shutter_curve: ShutterCurve::default(),
             background: Color::ZERO,
+            exposure: 0.0,
+            tone_mapper: ToneMapper::default(),
+            white_point: ToneMapping::default().white_point,
+            animation: CameraAnimation::default(),
         }
     }
 }
-pub struct Camera {
-    pub image_width: usize,
-    pub image_height: usize,
-    pub samples_per_pixel: i32,
-    pub max_depth: i32,
-    pub background: Color,
+/// Placement of the camera, the part of [`CameraSettings`] the live view can change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vup: Vec3,
+    pub vfov: FP,
+    pub defocus_angle: FP,
+    pub focus_dist: FP,
+}
+
+impl View {
+    fn lerp(&self, other: &View, t: FP) -> View {
+        let mix = |a: FP, b: FP| a + (b - a) * t;
+        View {
+            look_from: self.look_from + (other.look_from - self.look_from) * t,
+            look_at: self.look_at + (other.look_at - self.look_at) * t,
+            vup: self.vup + (other.vup - self.vup) * t,
+            vfov: mix(self.vfov, other.vfov),
+            defocus_angle: mix(self.defocus_angle, other.defocus_angle),
+            focus_dist: mix(self.focus_dist, other.focus_dist),
+        }
+    }
+}
+
+/// Where the rays of a [`View`] start and which way they go.
+#[derive(Clone, Copy)]
+struct Geometry {
     center: Point3,
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
-    defocus_angle: FP,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
 }
 
+pub struct Camera {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+    /// What rays leaving the scene see, `background` everywhere unless replaced
+    pub environment: Arc<dyn Environment>,
+    pub tone_mapping: ToneMapping,
+    shutter_open: FP,
+    shutter_close: FP,
+    shutter_curve: ShutterCurve,
+    view: View,
+    /// View at shutter close, for motion blur
+    end_view: Option<View>,
+    geometry: Geometry,
+}
+
 impl Camera {
     pub fn new(settings: CameraSettings) -> Self {
         let CameraSettings {
@@ -57,22 +142,97 @@ impl Camera {
             image_width,
             samples_per_pixel,
             max_depth,
+            roulette_depth,
             vfov,
             look_from,
             look_at,
+            end_look_from: _,
+            end_look_at: _,
             vup,
             defocus_angle,
             focus_dist,
+            shutter_open,
+            shutter_close,
+            shutter_curve,
             background,
+            exposure,
+            tone_mapper,
+            white_point,
+            animation: _,
         } = settings;
 
         let image_height = (image_width as FP / aspect_ratio) as usize;
+        let view = View {
+            look_from,
+            look_at,
+            vup,
+            vfov,
+            defocus_angle,
+            focus_dist,
+        };
+
+        let mut camera = Self {
+            image_width,
+            image_height,
+            samples_per_pixel,
+            max_depth,
+            roulette_depth,
+            environment: Arc::new(ConstantEnvironment::new(background)),
+            tone_mapping: ToneMapping {
+                exposure,
+                tone_mapper,
+                white_point,
+            },
+            shutter_open,
+            shutter_close,
+            shutter_curve,
+            view,
+            end_view: None,
+            geometry: Geometry {
+                center: look_from,
+                pixel00_loc: Point3::ZERO,
+                pixel_delta_u: Vec3::ZERO,
+                pixel_delta_v: Vec3::ZERO,
+                defocus_disk_u: Vec3::ZERO,
+                defocus_disk_v: Vec3::ZERO,
+            },
+        };
+        camera.set_view(view);
+        camera
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    /// Moves the camera, keeping the image size and render settings. Any motion blur of the
+    /// camera is dropped.
+    pub fn set_view(&mut self, view: View) {
+        self.view = view;
+        self.end_view = None;
+        self.geometry = self.geometry(&view);
+    }
+
+    /// Moves the camera from its view at shutter open to `end` by the time it closes.
+    pub fn set_end_view(&mut self, end: View) {
+        self.end_view = (end != self.view).then_some(end);
+    }
+
+    fn geometry(&self, view: &View) -> Geometry {
+        let View {
+            look_from,
+            look_at,
+            vup,
+            vfov,
+            defocus_angle,
+            focus_dist,
+        } = *view;
 
         let theta = degrees_to_radians(vfov);
         let h = (theta / 2.0).tan();
 
         let viewport_height = 2.0 * h * focus_dist;
-        let viewport_width = viewport_height * (image_width as FP / image_height as FP);
+        let viewport_width = viewport_height * (self.image_width as FP / self.image_height as FP);
 
         let w = (look_from - look_at).normalize();
         let u = vup.cross(&w).normalize();
@@ -82,57 +242,65 @@ impl Camera {
         let viewport_v = -viewport_height * v;
 
         let center = look_from;
-        let pixel_delta_u = viewport_u / image_width as FP;
-        let pixel_delta_v = viewport_v / image_height as FP;
+        let pixel_delta_u = viewport_u / self.image_width as FP;
+        let pixel_delta_v = viewport_v / self.image_height as FP;
 
         let viewport_upper_left = center - focus_dist * w - viewport_u * 0.5 - viewport_v * 0.5;
 
         let pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
 
         let defocus_radius = focus_dist * degrees_to_radians(defocus_angle / 2.0).tan();
-        let defocus_disk_u = u * defocus_radius;
-        let defocus_disk_v = v * defocus_radius;
 
-        Self {
-            image_width,
-            image_height,
-            samples_per_pixel,
-            max_depth,
-            background,
+        Geometry {
             center,
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
-            defocus_angle,
-            defocus_disk_u,
-            defocus_disk_v,
+            defocus_disk_u: u * defocus_radius,
+            defocus_disk_v: v * defocus_radius,
         }
     }
 
-    pub fn get_ray(&self, i: usize, j: usize) -> Ray {
-        let pixel_center =
-            self.pixel00_loc + (i as FP * self.pixel_delta_u) + (j as FP * self.pixel_delta_v);
-        let pixel_sample = pixel_center + self.pixel_sample_square();
+    pub fn center(&self) -> Point3 {
+        self.geometry.center
+    }
+
+    /// Ray through the image position (`x`, `y`), in pixels from the top left corner.
+    pub fn get_ray(&self, (x, y): (FP, FP), sampler: &mut dyn Sampler) -> Ray {
+        // The lens sample is drawn either way to keep the later dimensions in place
+        let lens_sample = sampler.get_2d();
+        let ray_time = self.shutter_time(sampler.get_1d());
+
+        let (view, geometry) = match &self.end_view {
+            Some(end) => {
+                let view = self.view.lerp(end, ray_time);
+                (view, self.geometry(&view))
+            }
+            None => (self.view, self.geometry),
+        };
 
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center
+        let pixel_sample = geometry.pixel00_loc
+            + ((x - 0.5) * geometry.pixel_delta_u)
+            + ((y - 0.5) * geometry.pixel_delta_v);
+        let ray_origin = if view.defocus_angle <= 0.0 {
+            geometry.center
         } else {
-            self.defocus_disk_sample()
+            let p = Vec3::random_in_unit_disk(lens_sample);
+            geometry.center + p.x * geometry.defocus_disk_u + p.y * geometry.defocus_disk_v
         };
         let ray_direction = pixel_sample - ray_origin;
-        let ray_time = rand::random();
 
         Ray::new(ray_origin, ray_direction).with_time(ray_time)
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = Vec3::random_in_unit_disk();
-        self.center + p.x * self.defocus_disk_u + p.y * self.defocus_disk_v
-    }
-
-    fn pixel_sample_square(&self) -> Vec3 {
-        let px = -0.5 + rand::random::<FP>();
-        let py = -0.5 + rand::random::<FP>();
-        px * self.pixel_delta_u + py * self.pixel_delta_v
+    /// Time between shutter open and close, distributed by the shutter curve, for the
+    /// uniform sample `u`.
+    fn shutter_time(&self, u: FP) -> FP {
+        let s = match self.shutter_curve {
+            ShutterCurve::Box => u,
+            ShutterCurve::Triangle if u < 0.5 => (0.5 * u).sqrt(),
+            ShutterCurve::Triangle => 1.0 - (0.5 * (1.0 - u)).sqrt(),
+        };
+        self.shutter_open + (self.shutter_close - self.shutter_open) * s
     }
 }
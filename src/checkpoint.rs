@@ -0,0 +1,296 @@
+use std::{
+    fmt, fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{common::FP, renderer::PixelState, vec3::Vec3};
+
+const MAGIC: &[u8; 8] = b"RTCKPT03";
+
+/// Where and how often [`render`](crate::renderer::render) saves its progress.
+#[derive(Debug, Clone)]
+pub struct CheckpointSettings {
+    pub path: PathBuf,
+    pub interval: Duration,
+    /// Hash of everything the samples depend on, see [`fingerprint`]
+    pub fingerprint: u64,
+    /// Continue from the samples already in `path`
+    pub resume: bool,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io { path: PathBuf, err: io::Error },
+    Format { path: PathBuf },
+    Mismatch { path: PathBuf },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io { path, err } => write!(f, "{}: {}", path.display(), err),
+            CheckpointError::Format { path } => {
+                write!(f, "{}: not a render checkpoint", path.display())
+            }
+            CheckpointError::Mismatch { path } => write!(
+                f,
+                "{}: checkpoint was rendered with a different scene, seed or settings",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+/// FNV-1a hash of a description of the scene and the settings that change the samples.
+/// The sample count isn't part of it, so a resumed render can add more samples.
+pub fn fingerprint(description: &str) -> u64 {
+    description.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Writes the accumulated `pixels` next to `path` first, so a crash while saving leaves the
+/// previous checkpoint intact.
+pub fn save(
+    path: &Path,
+    seed: u64,
+    fingerprint: u64,
+    width: usize,
+    height: usize,
+    pixels: &[PixelState],
+) -> io::Result<()> {
+    let temp_path = path.with_extension("checkpoint.tmp");
+    {
+        let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+        writer.write_all(MAGIC)?;
+        for value in [seed, fingerprint, width as u64, height as u64] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for pixel in pixels {
+            write_pixel(&mut writer, pixel)?;
+        }
+        writer.flush()?;
+    }
+    fs::rename(temp_path, path)
+}
+
+/// Reads the seed of the render stored in `path`.
+pub fn read_seed(path: &Path) -> Result<u64, CheckpointError> {
+    let mut reader = open(path)?;
+    read_u64(&mut reader).map_err(|err| io_error(path, err))
+}
+
+/// Loads the pixels of `path`, provided it was written by a render with `fingerprint`.
+pub fn load(
+    path: &Path,
+    fingerprint: u64,
+    width: usize,
+    height: usize,
+) -> Result<Vec<PixelState>, CheckpointError> {
+    let mut reader = open(path)?;
+    let read = |reader: &mut BufReader<fs::File>| -> io::Result<_> {
+        let _seed = read_u64(reader)?;
+        let header = [read_u64(reader)?, read_u64(reader)?, read_u64(reader)?];
+        if header != [fingerprint, width as u64, height as u64] {
+            return Ok(None);
+        }
+        (0..width * height)
+            .map(|_| read_pixel(reader))
+            .collect::<io::Result<Vec<PixelState>>>()
+            .map(Some)
+    };
+
+    match read(&mut reader) {
+        Ok(Some(pixels)) => Ok(pixels),
+        Ok(None) => Err(CheckpointError::Mismatch {
+            path: path.to_path_buf(),
+        }),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(CheckpointError::Format {
+            path: path.to_path_buf(),
+        }),
+        Err(err) => Err(io_error(path, err)),
+    }
+}
+
+fn io_error(path: &Path, err: io::Error) -> CheckpointError {
+    CheckpointError::Io {
+        path: path.to_path_buf(),
+        err,
+    }
+}
+
+/// Opens `path` and checks the magic bytes.
+fn open(path: &Path) -> Result<BufReader<fs::File>, CheckpointError> {
+    let mut reader = BufReader::new(fs::File::open(path).map_err(|err| io_error(path, err))?);
+    let mut magic = [0; MAGIC.len()];
+    match reader.read_exact(&mut magic) {
+        Ok(()) if &magic == MAGIC => Ok(reader),
+        Ok(()) => Err(CheckpointError::Format {
+            path: path.to_path_buf(),
+        }),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(CheckpointError::Format {
+            path: path.to_path_buf(),
+        }),
+        Err(err) => Err(io_error(path, err)),
+    }
+}
+
+fn write_pixel(writer: &mut impl Write, pixel: &PixelState) -> io::Result<()> {
+    let vectors = [
+        pixel.color,
+        pixel.aov.albedo,
+        pixel.aov.normal,
+        pixel.aov.position,
+    ];
+    for v in vectors {
+        for value in [v.x, v.y, v.z] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    let values = [
+        pixel.weight,
+        pixel.aov.depth,
+        pixel.stats.mean,
+        pixel.stats.m2,
+    ];
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.write_all(&pixel.stats.count.to_le_bytes())?;
+    writer.write_all(&pixel.aov.id.to_le_bytes())
+}
+
+fn read_pixel(reader: &mut impl Read) -> io::Result<PixelState> {
+    let mut pixel = PixelState {
+        color: read_vec3(reader)?,
+        ..Default::default()
+    };
+    pixel.aov.albedo = read_vec3(reader)?;
+    pixel.aov.normal = read_vec3(reader)?;
+    pixel.aov.position = read_vec3(reader)?;
+    pixel.weight = read_fp(reader)?;
+    pixel.aov.depth = read_fp(reader)?;
+    pixel.stats.mean = read_fp(reader)?;
+    pixel.stats.m2 = read_fp(reader)?;
+
+    pixel.stats.count = read_u32(reader)?;
+    pixel.aov.id = read_u32(reader)?;
+
+    Ok(pixel)
+}
+
+fn read_vec3(reader: &mut dyn Read) -> io::Result<Vec3> {
+    Ok(Vec3::new(
+        read_fp(reader)?,
+        read_fp(reader)?,
+        read_fp(reader)?,
+    ))
+}
+
+fn read_fp(reader: &mut dyn Read) -> io::Result<FP> {
+    let mut bytes = [0; std::mem::size_of::<FP>()];
+    reader.read_exact(&mut bytes)?;
+    Ok(FP::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aov::AOVSample, renderer::RunningStats};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.checkpoint", name, std::process::id()))
+    }
+
+    fn pixels() -> Vec<PixelState> {
+        (0..6)
+            .map(|i| {
+                let i = i as FP;
+                PixelState {
+                    color: Vec3::new(i, 0.5 * i, 1.0 / (i + 1.0)),
+                    weight: 3.0 + i,
+                    aov: AOVSample {
+                        albedo: Vec3::splat(0.1 * i),
+                        normal: Vec3::new(0.0, 1.0, -i),
+                        depth: 10.0 * i,
+                        position: Vec3::new(-i, i, 2.0),
+                        id: i as u32,
+                    },
+                    stats: RunningStats {
+                        count: 7 + i as u32,
+                        mean: 0.25 * i,
+                        m2: 0.125 * i,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn bytes(pixels: &[PixelState]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for pixel in pixels {
+            write_pixel(&mut bytes, pixel).expect("Should've written to memory");
+        }
+        bytes
+    }
+
+    #[test]
+    fn loads_what_was_saved() {
+        let path = temp_path("loads-what-was-saved");
+        let pixels = pixels();
+        save(&path, 42, 1234, 3, 2, &pixels).expect("Should've saved the checkpoint");
+
+        let seed = read_seed(&path);
+        let loaded = load(&path, 1234, 3, 2);
+        fs::remove_file(&path).expect("Should've removed the checkpoint");
+
+        assert_eq!(seed.expect("Should've read the seed"), 42);
+        assert_eq!(
+            bytes(&loaded.expect("Should've loaded the checkpoint")),
+            bytes(&pixels)
+        );
+    }
+
+    #[test]
+    fn rejects_other_renders() {
+        let path = temp_path("rejects-other-renders");
+        save(&path, 42, 1234, 3, 2, &pixels()).expect("Should've saved the checkpoint");
+
+        let other_fingerprint = load(&path, 4321, 3, 2);
+        let other_size = load(&path, 1234, 2, 3);
+        fs::remove_file(&path).expect("Should've removed the checkpoint");
+
+        assert!(matches!(
+            other_fingerprint,
+            Err(CheckpointError::Mismatch { .. })
+        ));
+        assert!(matches!(other_size, Err(CheckpointError::Mismatch { .. })));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = temp_path("rejects-other-files");
+        fs::write(&path, b"P6\n3 2\n255\n").expect("Should've written the file");
+
+        let loaded = load(&path, 1234, 3, 2);
+        fs::remove_file(&path).expect("Should've removed the file");
+
+        assert!(matches!(loaded, Err(CheckpointError::Format { .. })));
+    }
+}
@@ -1,27 +1,145 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
 use crate::{common::FP, interval::Interval, vec3::Color};
 
 #[inline]
-fn linear_to_gamma(linear_component: FP) -> FP {
-    linear_component.powf(1.0 / 2.2)
+fn linear_to_srgb(linear_component: FP) -> FP {
+    if linear_component <= 0.0031308 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1.0 / 2.4) - 0.055
+    }
 }
 #[inline]
-fn gamma_to_linear(gamma_component: FP) -> FP {
-    gamma_component.powf(2.2)
+fn srgb_to_linear(srgb_component: FP) -> FP {
+    if srgb_component <= 0.04045 {
+        srgb_component / 12.92
+    } else {
+        ((srgb_component + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapper {
+    /// Clips everything above 1
+    #[default]
+    Clamp,
+    /// x / (1 + x)
+    Reinhard,
+    /// Reinhard that reaches white at the white point
+    ExtendedReinhard,
+    /// Narkowicz fit of the ACES filmic curve
+    Aces,
+    /// AgX base look, desaturates bright colors towards white
+    Agx,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapping {
+    /// Exposure compensation in stops, every stop doubles the radiance
+    pub exposure: FP,
+    pub tone_mapper: ToneMapper,
+    /// Radiance mapped to white by [`ToneMapper::ExtendedReinhard`]
+    pub white_point: FP,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::default(),
+            white_point: 4.0,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Maps linear radiance into the displayable linear `[0, 1]` range.
+    pub fn apply(&self, color: &Color) -> Color {
+        let c = *color * self.exposure.exp2();
+
+        match self.tone_mapper {
+            ToneMapper::Clamp => c,
+            ToneMapper::Reinhard => per_channel(c, |x| x / (1.0 + x)),
+            ToneMapper::ExtendedReinhard => {
+                let white_sq = self.white_point * self.white_point;
+                per_channel(c, |x| x * (1.0 + x / white_sq) / (1.0 + x))
+            }
+            ToneMapper::Aces => per_channel(c, |x| {
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            }),
+            ToneMapper::Agx => agx(c),
+        }
+    }
+
+    /// Tone maps `rgb` and encodes it as 8-bit sRGB.
+    pub fn color_to_rgb(&self, rgb: &Color) -> [u8; 3] {
+        let intensity = Interval::new(0.0, 0.999);
+        let mapped = self.apply(rgb);
+        [
+            (256.0 * intensity.clamp(linear_to_srgb(mapped.x))) as u8,
+            (256.0 * intensity.clamp(linear_to_srgb(mapped.y))) as u8,
+            (256.0 * intensity.clamp(linear_to_srgb(mapped.z))) as u8,
+        ]
+    }
 }
 
-pub fn color_to_rgb(rgb: &Color) -> [u8; 3] {
-    let intensity = Interval::new(0.0, 0.999);
-    [
-        (256.0 * intensity.clamp(linear_to_gamma(rgb.x))) as u8,
-        (256.0 * intensity.clamp(linear_to_gamma(rgb.y))) as u8,
-        (256.0 * intensity.clamp(linear_to_gamma(rgb.z))) as u8,
-    ]
+/// Polynomial fit of the AgX base contrast curve by Benjamin Wrensch.
+fn agx(color: Color) -> Color {
+    const MIN_EV: FP = -12.47393;
+    const MAX_EV: FP = 4.026069;
+
+    // Inset into the AgX working space
+    let c = Color::new(
+        0.842479062253094 * color.x + 0.0784335999999992 * color.y + 0.0792237451477643 * color.z,
+        0.0423282422610123 * color.x + 0.878468636469772 * color.y + 0.0791661274605434 * color.z,
+        0.0423756549057051 * color.x + 0.0784336 * color.y + 0.879142973793104 * color.z,
+    );
+
+    let contrast = |x: FP| {
+        let x = (x.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+    let c = per_channel(c, contrast);
+
+    // Outset back and undo the display encoding the curve was fitted with
+    let c = Color::new(
+        1.19687900512017 * c.x - 0.0980208811401368 * c.y - 0.0990297440797205 * c.z,
+        -0.0528968517574562 * c.x + 1.15190312990417 * c.y - 0.0989611768448433 * c.z,
+        -0.0529716355144438 * c.x - 0.0980434501171241 * c.y + 1.15107367264116 * c.z,
+    );
+    per_channel(c, |x| x.max(0.0).powf(2.2))
+}
+
+#[inline]
+fn per_channel(c: Color, f: impl Fn(FP) -> FP) -> Color {
+    Color::new(f(c.x), f(c.y), f(c.z))
+}
+
+/// Relative luminance of linear Rec. 709 primaries.
+pub fn luminance(color: &Color) -> FP {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// Black through red and yellow to white as `t` goes from 0 to 1.
+pub fn heat_color(t: FP) -> Color {
+    let t = t.clamp(0.0, 1.0) * 3.0;
+    Color::new(
+        t.min(1.0),
+        (t - 1.0).clamp(0.0, 1.0),
+        (t - 2.0).clamp(0.0, 1.0),
+    )
 }
 
 pub fn rgb_to_color(r: u8, g: u8, b: u8) -> Color {
     Color::new(
-        gamma_to_linear((r as FP) / 255.0),
-        gamma_to_linear((g as FP) / 255.0),
-        gamma_to_linear((b as FP) / 255.0),
+        srgb_to_linear((r as FP) / 255.0),
+        srgb_to_linear((g as FP) / 255.0),
+        srgb_to_linear((b as FP) / 255.0),
     )
 }
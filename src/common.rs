@@ -1,3 +1,14 @@
+use std::cell::RefCell;
+
+use rand::{
+    distributions::{
+        uniform::{SampleRange, SampleUniform},
+        Distribution, Standard,
+    },
+    Rng, SeedableRng,
+};
+use rand_pcg::Pcg32;
+
 pub type FP = f64;
 
 pub const PI: FP = std::f64::consts::PI as FP;
@@ -6,3 +17,36 @@ pub const PI: FP = std::f64::consts::PI as FP;
 pub fn degrees_to_radians(degrees: FP) -> FP {
     degrees * PI / 180.0
 }
+
+thread_local! {
+    static RNG: RefCell<Pcg32> = RefCell::new(Pcg32::from_entropy());
+}
+
+/// Restarts the random sequence of the current thread from a state derived from
+/// (`seed`, `pixel`, `sample`), so what gets drawn afterwards doesn't depend on which
+/// rayon thread happens to draw it.
+pub fn seed_rng(seed: u64, pixel: u64, sample: u64) {
+    let state = splitmix64(seed ^ splitmix64(pixel));
+    RNG.with(|rng| *rng.borrow_mut() = Pcg32::new(state, sample));
+}
+
+/// Draws from the seedable generator of the current thread, see [`seed_rng`].
+#[inline]
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+#[inline]
+pub fn random_range<T: SampleUniform, R: SampleRange<T>>(range: R) -> T {
+    RNG.with(|rng| rng.borrow_mut().gen_range(range))
+}
+
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
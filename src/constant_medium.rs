@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use crate::{
     aabb::AABB,
-    common::FP,
+    common::{self, FP},
     hittable::{HitRecord, Hittable},
     interval::Interval,
     material::{Isotropic, Material},
@@ -45,7 +45,7 @@ impl Hittable for ConstantMedium {
 
                     let ray_length = r.direction.length();
                     let distance_inside_boundary = (hit2.t - hit1.t) * ray_length;
-                    let hit_distance = self.neg_inv_density * rand::random::<FP>().ln();
+                    let hit_distance = self.neg_inv_density * common::random::<FP>().ln();
 
                     if hit_distance <= distance_inside_boundary {
                         let t = hit1.t + hit_distance / ray_length;
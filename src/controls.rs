@@ -0,0 +1,236 @@
+use fltk::{
+    app::{self, MouseButton},
+    enums::{Event, Key},
+};
+
+use crate::{
+    aov::AOV,
+    camera::View,
+    common::{degrees_to_radians, FP},
+    vec3::{Point3, Vec3},
+};
+
+/// Radians the camera orbits per pixel dragged.
+const ORBIT_SPEED: FP = 0.005;
+/// Fraction of the distance to `look_at` moved per key press.
+const FLY_SPEED: FP = 0.05;
+const ZOOM_FACTOR: FP = 1.1;
+
+pub const HELP: &str = "Drag to orbit, right drag to pan, scroll to zoom, WASD/QE to fly, \
+    -/= for the field of view, ,/. for the focus distance, F to focus on the target, \
+    P to print the view and R to reset it. Space pauses, X saves a PNG and an EXR and \
+    1/2/3 show the render, the normals or the albedo";
+
+/// What the live view shows and saves.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LiveView {
+    #[default]
+    Beauty,
+    Normal,
+    Albedo,
+}
+
+impl LiveView {
+    /// The output pass showing the same, `None` for the render itself.
+    pub fn aov(&self) -> Option<AOV> {
+        match self {
+            LiveView::Beauty => None,
+            LiveView::Normal => Some(AOV::Normal),
+            LiveView::Albedo => Some(AOV::Albedo),
+        }
+    }
+}
+
+/// Live view actions besides moving the camera.
+pub enum LiveCommand {
+    Save,
+    TogglePause,
+    Show(LiveView),
+}
+
+impl LiveCommand {
+    pub fn from_key(key: Key) -> Option<Self> {
+        match key_char(key)?.to_ascii_lowercase() {
+            'x' => Some(LiveCommand::Save),
+            ' ' => Some(LiveCommand::TogglePause),
+            '1' => Some(LiveCommand::Show(LiveView::Beauty)),
+            '2' => Some(LiveCommand::Show(LiveView::Normal)),
+            '3' => Some(LiveCommand::Show(LiveView::Albedo)),
+            _ => None,
+        }
+    }
+}
+
+/// Special keys truncate to a char as well, so only the ones that round trip count.
+fn key_char(key: Key) -> Option<char> {
+    key.to_char().filter(|&c| Key::from_char(c) == key)
+}
+
+/// Mouse and keyboard camera movement for the live view.
+pub struct CameraControls {
+    view: View,
+    initial_view: View,
+    image_height: usize,
+    drag: Option<(i32, i32)>,
+    changed: bool,
+}
+
+impl CameraControls {
+    pub fn new(view: View, image_height: usize) -> Self {
+        Self {
+            view,
+            initial_view: view,
+            image_height,
+            drag: None,
+            changed: false,
+        }
+    }
+
+    /// Jumps to `view`, which the reset key goes back to from then on.
+    pub fn set_view(&mut self, view: View) {
+        self.view = view;
+        self.initial_view = view;
+        self.changed = false;
+    }
+
+    /// The new view, if an event changed it since the last call.
+    pub fn take_change(&mut self) -> Option<View> {
+        std::mem::take(&mut self.changed).then_some(self.view)
+    }
+
+    /// Updates the view from a window event, returning whether the event was used.
+    pub fn handle(&mut self, event: Event) -> bool {
+        match event {
+            // Accepting these is what makes the window send drags and key presses
+            Event::Focus | Event::Unfocus => true,
+            Event::Push => {
+                self.drag = Some(app::event_coords());
+                true
+            }
+            Event::Released => {
+                self.drag = None;
+                true
+            }
+            Event::Drag => {
+                let (x, y) = app::event_coords();
+                if let Some((last_x, last_y)) = self.drag.replace((x, y)) {
+                    let (dx, dy) = ((x - last_x) as FP, (y - last_y) as FP);
+                    match app::event_mouse_button() {
+                        MouseButton::Left => self.orbit(dx, dy),
+                        _ => self.pan(dx, dy),
+                    }
+                }
+                true
+            }
+            Event::MouseWheel => {
+                let factor = ZOOM_FACTOR.powi(app::event_dy_value());
+                self.zoom(factor);
+                true
+            }
+            Event::KeyDown => self.key(app::event_key()),
+            _ => false,
+        }
+    }
+
+    fn key(&mut self, key: Key) -> bool {
+        let Some(c) = key_char(key) else {
+            return false;
+        };
+        let forward = self.view.look_at - self.view.look_from;
+        let step = FLY_SPEED * forward.length();
+        let forward = forward.normalize();
+        let right = forward.cross(&self.view.vup).normalize();
+        let up = self.view.vup.normalize();
+
+        match c.to_ascii_lowercase() {
+            'w' => self.translate(step * forward),
+            's' => self.translate(-step * forward),
+            'd' => self.translate(step * right),
+            'a' => self.translate(-step * right),
+            'e' => self.translate(step * up),
+            'q' => self.translate(-step * up),
+            '-' => self.set_vfov(self.view.vfov + 2.0),
+            '=' | '+' => self.set_vfov(self.view.vfov - 2.0),
+            ',' => self.set_focus_dist(self.view.focus_dist / ZOOM_FACTOR),
+            '.' => self.set_focus_dist(self.view.focus_dist * ZOOM_FACTOR),
+            'f' => self.set_focus_dist((self.view.look_at - self.view.look_from).length()),
+            'r' => {
+                self.view = self.initial_view;
+                self.changed = true;
+            }
+            'p' => print_view(&self.view),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Rotates `look_from` around `look_at`, stopping short of the poles.
+    fn orbit(&mut self, dx: FP, dy: FP) {
+        let up = self.view.vup.normalize();
+        let offset = self.view.look_from - self.view.look_at;
+        let offset = rotate(&offset, &up, -dx * ORBIT_SPEED);
+
+        let right = up.cross(&offset).normalize();
+        let pitched = rotate(&offset, &right, -dy * ORBIT_SPEED);
+        let offset = if pitched.normalize().dot(&up).abs() < 0.99 {
+            pitched
+        } else {
+            offset
+        };
+
+        self.view.look_from = self.view.look_at + offset;
+        self.changed = true;
+    }
+
+    /// Moves the camera sideways so the point at `look_at` follows the mouse.
+    fn pan(&mut self, dx: FP, dy: FP) {
+        let forward = self.view.look_at - self.view.look_from;
+        let distance = forward.length();
+        let right = forward.cross(&self.view.vup).normalize();
+        let up = right.cross(&forward).normalize();
+        let viewport_height = 2.0 * (degrees_to_radians(self.view.vfov) / 2.0).tan() * distance;
+        let pixel_size = viewport_height / self.image_height as FP;
+
+        self.translate(pixel_size * (-dx * right + dy * up));
+    }
+
+    fn zoom(&mut self, factor: FP) {
+        let offset = self.view.look_from - self.view.look_at;
+        if factor < 1.0 && offset.length() < 1e-3 {
+            return;
+        }
+        self.view.look_from = self.view.look_at + factor * offset;
+        self.changed = true;
+    }
+
+    fn translate(&mut self, delta: Vec3) {
+        self.view.look_from += delta;
+        self.view.look_at += delta;
+        self.changed = true;
+    }
+
+    fn set_vfov(&mut self, vfov: FP) {
+        self.view.vfov = vfov.clamp(1.0, 170.0);
+        self.changed = true;
+    }
+
+    fn set_focus_dist(&mut self, focus_dist: FP) {
+        self.view.focus_dist = focus_dist.max(1e-3);
+        self.changed = true;
+    }
+}
+
+/// Rodrigues' rotation of `v` by `angle` around the unit vector `axis`.
+fn rotate(v: &Vec3, axis: &Vec3, angle: FP) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    *v * cos + axis.cross(v) * sin + *axis * axis.dot(v) * (1.0 - cos)
+}
+
+/// Prints the view in the form of a scene file `[camera]` table.
+fn print_view(view: &View) {
+    let array = |p: &Point3| format!("[{:.3}, {:.3}, {:.3}]", p.x, p.y, p.z);
+    println!("look_from = {}", array(&view.look_from));
+    println!("look_at = {}", array(&view.look_at));
+    println!("vfov = {:.3}", view.vfov);
+    println!("focus_dist = {:.3}", view.focus_dist);
+}
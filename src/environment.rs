@@ -0,0 +1,262 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use image::{codecs::hdr::HdrDecoder, DynamicImage, ImageError};
+
+use crate::{
+    color::{luminance, rgb_to_color},
+    common::{degrees_to_radians, FP, PI},
+    vec3::{Color, Vec3},
+};
+
+/// Light arriving from infinitely far away, seen by rays that leave the scene.
+pub trait Environment: Sync + Send {
+    /// Light arriving along `direction`, coming from the opposite way.
+    fn radiance(&self, direction: &Vec3) -> Color;
+    /// Solid angle density of sampling `direction` with [`Environment::sample`].
+    fn pdf_value(&self, _direction: &Vec3) -> FP {
+        1.0 / (4.0 * PI)
+    }
+    /// Samples a direction light arrives along with the uniform sample `u`.
+    fn sample(&self, u: (FP, FP)) -> Vec3 {
+        Vec3::random_on_unit_sphere(u)
+    }
+    /// Whether next event estimation should sample it, only worth it when the light is uneven.
+    fn is_sampled(&self) -> bool {
+        false
+    }
+}
+
+/// The same color in every direction.
+pub struct ConstantEnvironment {
+    color: Color,
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn radiance(&self, _direction: &Vec3) -> Color {
+        self.color
+    }
+}
+
+/// Blends from `bottom` straight down to `top` straight up.
+pub struct GradientEnvironment {
+    bottom: Color,
+    top: Color,
+}
+
+impl GradientEnvironment {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let a = 0.5 * (direction.normalize().y + 1.0);
+        (1.0 - a) * self.bottom + a * self.top
+    }
+}
+
+/// Equirectangular image around the scene, importance sampled by the luminance of its pixels.
+pub struct ImageEnvironment {
+    pixels: Vec<Color>,
+    width: usize,
+    height: usize,
+    /// Turn around the Y axis in radians
+    rotation: FP,
+    distribution: Distribution2D,
+}
+
+impl ImageEnvironment {
+    /// Float images like HDR and EXR are taken as linear, others as sRGB. `rotation` turns
+    /// the image around the Y axis in degrees.
+    pub fn open(path: &str, rotation: FP, intensity: FP) -> Result<Self, ImageError> {
+        let (mut pixels, width, height) = read_pixels(path)?;
+        for pixel in &mut pixels {
+            *pixel *= intensity;
+        }
+
+        // Rows near the poles cover less of the sphere
+        let weights = pixels
+            .chunks_exact(width)
+            .enumerate()
+            .flat_map(|(row, colors)| {
+                let sin_theta = (PI * (row as FP + 0.5) / height as FP).sin();
+                colors
+                    .iter()
+                    .map(move |c| luminance(c).max(0.0) * sin_theta)
+            })
+            .collect::<Vec<FP>>();
+
+        Ok(Self {
+            distribution: Distribution2D::new(&weights, width),
+            pixels,
+            width,
+            height,
+            rotation: degrees_to_radians(rotation),
+        })
+    }
+
+    /// Image coordinates of `direction`, `u` going around and `v` from the top down.
+    fn direction_to_uv(&self, direction: &Vec3) -> (FP, FP) {
+        let d = direction.normalize();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + PI - self.rotation;
+        (phi.rem_euclid(2.0 * PI) / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(&self, (u, v): (FP, FP)) -> Vec3 {
+        let theta = v * PI;
+        let phi = u * 2.0 * PI - PI + self.rotation;
+        let sin_theta = theta.sin();
+        Vec3::new(sin_theta * phi.cos(), theta.cos(), -sin_theta * phi.sin())
+    }
+}
+
+/// Linear pixels of the image at `path`, with its width and height.
+fn read_pixels(path: &str) -> Result<(Vec<Color>, usize, usize), ImageError> {
+    let is_hdr = Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+    // The generic decoder clamps Radiance HDR files to 8 bits
+    if is_hdr {
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()?
+            .iter()
+            .map(|p| Color::new(p[0] as FP, p[1] as FP, p[2] as FP))
+            .collect();
+        return Ok((pixels, metadata.width as usize, metadata.height as usize));
+    }
+
+    let image = image::io::Reader::open(path)?.decode()?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => image
+            .into_rgb32f()
+            .pixels()
+            .map(|p| Color::new(p[0] as FP, p[1] as FP, p[2] as FP))
+            .collect(),
+        _ => image
+            .into_rgb8()
+            .pixels()
+            .map(|p| rgb_to_color(p[0], p[1], p[2]))
+            .collect(),
+    };
+    Ok((pixels, width, height))
+}
+
+impl Environment for ImageEnvironment {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(direction);
+        let i = ((u * self.width as FP) as usize).min(self.width - 1);
+        let j = ((v * self.height as FP) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> FP {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // From the density over the image to the one over the sphere
+        self.distribution.pdf((u, v)) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn sample(&self, u: (FP, FP)) -> Vec3 {
+        self.uv_to_direction(self.distribution.sample(u))
+    }
+
+    fn is_sampled(&self) -> bool {
+        !self.distribution.is_black()
+    }
+}
+
+/// Piecewise constant density over `[0, 1)`, sampled by inverting its CDF.
+struct Distribution1D {
+    func: Vec<FP>,
+    cdf: Vec<FP>,
+    integral: FP,
+}
+
+impl Distribution1D {
+    fn new(func: &[FP]) -> Self {
+        let n = func.len() as FP;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in func {
+            cdf.push(cdf.last().unwrap() + f / n);
+        }
+        let integral = *cdf.last().unwrap();
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // All zero falls back to uniform
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as FP / n
+            };
+        }
+        Self {
+            func: func.to_vec(),
+            cdf,
+            integral,
+        }
+    }
+
+    /// Position in `[0, 1)` and the index of the piece it's in.
+    fn sample(&self, u: FP) -> (FP, usize) {
+        let i = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.func.len() - 1);
+        let (c0, c1) = (self.cdf[i], self.cdf[i + 1]);
+        let offset = if c1 > c0 { (u - c0) / (c1 - c0) } else { 0.0 };
+        ((i as FP + offset) / self.func.len() as FP, i)
+    }
+
+    fn pdf(&self, i: usize) -> FP {
+        if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise constant density over `[0, 1)²`, picking a row first and then a column in it.
+struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    fn new(weights: &[FP], width: usize) -> Self {
+        let rows = weights
+            .chunks_exact(width)
+            .map(Distribution1D::new)
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(&rows.iter().map(|r| r.integral).collect::<Vec<_>>());
+        Self { rows, marginal }
+    }
+
+    fn sample(&self, (u1, u2): (FP, FP)) -> (FP, FP) {
+        let (v, row) = self.marginal.sample(u1);
+        let (u, _) = self.rows[row].sample(u2);
+        (u, v)
+    }
+
+    fn pdf(&self, (u, v): (FP, FP)) -> FP {
+        let row = ((v * self.rows.len() as FP) as usize).min(self.rows.len() - 1);
+        let columns = &self.rows[row];
+        let column = ((u * columns.func.len() as FP) as usize).min(columns.func.len() - 1);
+        self.marginal.pdf(row) * columns.pdf(column)
+    }
+
+    fn is_black(&self) -> bool {
+        self.marginal.integral <= 0.0
+    }
+}
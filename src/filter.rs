@@ -0,0 +1,169 @@
+use clap::ValueEnum;
+
+use crate::{common::FP, common::PI, vec3::Color};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FilterType {
+    /// Every sample only counts for its own pixel
+    #[default]
+    Box,
+    /// Linear falloff
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3
+    Mitchell,
+    /// Windowed sinc with as many lobes as the radius
+    Lanczos,
+}
+
+impl FilterType {
+    pub fn default_radius(&self) -> FP {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Tent => 1.0,
+            FilterType::Gaussian => 1.5,
+            FilterType::Mitchell => 2.0,
+            FilterType::Lanczos => 3.0,
+        }
+    }
+}
+
+/// Separable reconstruction filter, weighting samples by their offset from a pixel center.
+#[derive(Debug, Clone, Copy)]
+pub struct Filter {
+    pub kind: FilterType,
+    pub radius: FP,
+}
+
+impl Filter {
+    pub fn new(kind: FilterType, radius: Option<FP>) -> Self {
+        Self {
+            kind,
+            radius: radius.unwrap_or(kind.default_radius()),
+        }
+    }
+
+    /// Pixels a sample reaches beyond its own one in every direction.
+    pub fn margin(&self) -> usize {
+        (self.radius - 0.5).max(0.0).ceil() as usize
+    }
+
+    pub fn evaluate(&self, dx: FP, dy: FP) -> FP {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: FP) -> FP {
+        let r = self.radius;
+        match self.kind {
+            // Half open, so a sample on a pixel border only lands in one pixel
+            FilterType::Box => {
+                if (-r..r).contains(&x) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            FilterType::Tent => (r - x.abs()).max(0.0),
+            FilterType::Gaussian => {
+                let sigma = r / 3.0;
+                let gaussian = |x: FP| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(r)).max(0.0)
+            }
+            FilterType::Mitchell => {
+                const B: FP = 1.0 / 3.0;
+                const C: FP = 1.0 / 3.0;
+                let x = (2.0 * x / r).abs();
+                if x < 1.0 {
+                    ((12.0 - 9.0 * B - 6.0 * C) * x * x * x
+                        + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+                        + (6.0 - 2.0 * B))
+                        / 6.0
+                } else if x < 2.0 {
+                    ((-B - 6.0 * C) * x * x * x
+                        + (6.0 * B + 30.0 * C) * x * x
+                        + (-12.0 * B - 48.0 * C) * x
+                        + (8.0 * B + 24.0 * C))
+                        / 6.0
+                } else {
+                    0.0
+                }
+            }
+            FilterType::Lanczos => {
+                if x.abs() >= r {
+                    0.0
+                } else {
+                    sinc(x) * sinc(x / r)
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: FP) -> FP {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Filter weighted sample sums for a window of pixels, a tile plus the margin its samples
+/// reach into, so tiles can be rendered in parallel and merged afterwards.
+pub struct SplatBuffer {
+    x0: isize,
+    y0: isize,
+    width: usize,
+    sums: Vec<(Color, FP)>,
+}
+
+impl SplatBuffer {
+    /// Covers the pixels `x0..x1` by `y0..y1` and the margin of `filter` around them.
+    pub fn new(filter: &Filter, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> Self {
+        let margin = filter.margin();
+        let width = x1 - x0 + 2 * margin;
+        let height = y1 - y0 + 2 * margin;
+        Self {
+            x0: x0 as isize - margin as isize,
+            y0: y0 as isize - margin as isize,
+            width,
+            sums: vec![(Color::ZERO, 0.0); width * height],
+        }
+    }
+
+    /// Adds `color`, sampled at the image position (`x`, `y`), to the pixels around it.
+    pub fn splat(&mut self, filter: &Filter, (x, y): (FP, FP), color: Color) {
+        let margin = filter.margin() as isize;
+        let (px, py) = (x.floor() as isize, y.floor() as isize);
+
+        for j in (py - margin)..=(py + margin) {
+            for i in (px - margin)..=(px + margin) {
+                let weight = filter.evaluate(x - (i as FP + 0.5), y - (j as FP + 0.5));
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = (j - self.y0) as usize * self.width + (i - self.x0) as usize;
+                let (sum, weight_sum) = &mut self.sums[index];
+                *sum += weight * color;
+                *weight_sum += weight;
+            }
+        }
+    }
+
+    /// The sums of every pixel inside the `width` by `height` image, with its pixel index.
+    pub fn pixels(
+        &self,
+        width: usize,
+        height: usize,
+    ) -> impl Iterator<Item = (usize, Color, FP)> + '_ {
+        self.sums
+            .iter()
+            .enumerate()
+            .filter_map(move |(k, &(sum, weight))| {
+                let i = self.x0 + (k % self.width) as isize;
+                let j = self.y0 + (k / self.width) as isize;
+                let inside = (0..width as isize).contains(&i) && (0..height as isize).contains(&j);
+                inside.then(|| (j as usize * width + i as usize, sum, weight))
+            })
+    }
+}
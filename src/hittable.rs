@@ -3,6 +3,7 @@ use crate::{
     common::{degrees_to_radians, FP},
     interval::Interval,
     material::Material,
+    matrix::{Mat4, MatrixMotion},
     ray::Ray,
     vec3::{Point3, Vec3},
 };
@@ -16,6 +17,8 @@ pub struct HitRecord<'a> {
     pub u: FP,
     pub v: FP,
     pub front_face: bool,
+    /// Place of the hit object in the scene counting from one, zero when it wasn't numbered
+    pub object_id: u32,
 }
 
 impl<'a> HitRecord<'a> {
@@ -33,6 +36,7 @@ impl<'a> HitRecord<'a> {
             u: 0.0,
             v: 0.0,
             front_face,
+            object_id: 0,
         }
     }
     pub fn with_uvs(mut self, u: FP, v: FP) -> Self {
@@ -45,6 +49,21 @@ impl<'a> HitRecord<'a> {
 pub trait Hittable: Sync + Send {
     fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord>;
     fn bounding_box(&self) -> AABB;
+
+    /// Whether this object emits light and can be sampled through [`Hittable::random`].
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    /// Solid angle density of sampling `direction` from `origin` with [`Hittable::random`].
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> FP {
+        0.0
+    }
+    /// Samples a direction from `origin` towards this object with the uniform sample `u`.
+    fn random(&self, _origin: &Point3, _u: (FP, FP)) -> Vec3 {
+        Vec3::RIGHT
+    }
+    /// Adds the emissive objects this one is made of to `lights`.
+    fn collect_lights(&self, _lights: &mut HittableList) {}
 }
 
 #[derive(Default)]
@@ -57,6 +76,37 @@ impl HittableList {
         self.bbox = AABB::new_from_aabbs(self.bbox, object.bounding_box());
         self.objects.push(Arc::clone(&object));
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Numbers the objects of this list from one in order, for the ID output pass.
+    pub fn with_ids(self) -> HittableList {
+        let mut list = HittableList::default();
+        for (index, object) in self.objects.into_iter().enumerate() {
+            list.add(Arc::new(Tagged::new(object, index as u32 + 1)));
+        }
+        list
+    }
+
+    /// Collects the emissive objects of this list so they can be sampled directly.
+    pub fn lights(&self) -> HittableList {
+        let mut lights = HittableList::default();
+        self.collect_lights(&mut lights);
+        lights
+    }
+}
+
+/// Adds the emissive ones of `objects` to `lights`, looking inside the others.
+pub fn collect_lights(objects: &[Arc<dyn Hittable>], lights: &mut HittableList) {
+    for object in objects {
+        if object.is_emissive() {
+            lights.add(Arc::clone(object));
+        } else {
+            object.collect_lights(lights);
+        }
+    }
 }
 impl Hittable for HittableList {
     fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord> {
@@ -76,6 +126,177 @@ impl Hittable for HittableList {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> FP {
+        let weight = 1.0 / self.objects.len() as FP;
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(origin, direction))
+            .sum()
+    }
+
+    fn random(&self, origin: &Point3, (u1, u2): (FP, FP)) -> Vec3 {
+        // Picks the object with the first dimension and stretches the rest of it back to [0, 1)
+        let scaled = u1 * self.objects.len() as FP;
+        let index = (scaled as usize).min(self.objects.len() - 1);
+        self.objects[index].random(origin, (scaled - index as FP, u2))
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        collect_lights(&self.objects, lights);
+    }
+}
+
+/// `object` with its hits marked with `id`, so output passes can tell it apart.
+pub struct Tagged {
+    object: Arc<dyn Hittable>,
+    id: u32,
+}
+impl Tagged {
+    pub fn new(object: Arc<dyn Hittable>, id: u32) -> Self {
+        Self { object, id }
+    }
+}
+impl Hittable for Tagged {
+    fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let mut hit = self.object.hit(r, ray_t)?;
+        hit.object_id = self.id;
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.object.bounding_box()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.object.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> FP {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, u: (FP, FP)) -> Vec3 {
+        self.object.random(origin, u)
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.object.collect_lights(lights);
+    }
+}
+
+/// Instance of `object` placed by an affine transform. However many transforms the matrix is
+/// composed of, rays only get transformed once, and `object` can be shared by many instances.
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+    /// Inverse transpose, keeping normals perpendicular under non-uniform scaling
+    normal_matrix: Mat4,
+    /// Motion from `matrix` at shutter open, the matrices are then worked out for every ray
+    motion: Option<MatrixMotion>,
+    bbox: AABB,
+}
+impl Transform {
+    /// `None` when `matrix` can't be inverted.
+    pub fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        let bbox = matrix.transform_aabb(&object.bounding_box());
+        Some(Self {
+            object,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+            motion: None,
+            bbox,
+        })
+    }
+
+    /// Moves the instance to `end` by the time the shutter closes. `None` when `end` can't be
+    /// inverted.
+    pub fn with_end(self, end: Mat4) -> Option<Self> {
+        end.inverse()?;
+        let motion = MatrixMotion::new(&self.matrix, &end);
+        Some(Self {
+            bbox: motion.transform_aabb(&self.object.bounding_box()),
+            motion: Some(motion),
+            ..self
+        })
+    }
+    /// The same placement applied to another object.
+    fn with_object(&self, object: Arc<dyn Hittable>) -> Self {
+        Self {
+            bbox: self.matrix.transform_aabb(&object.bounding_box()),
+            object,
+            motion: None,
+            ..*self
+        }
+    }
+}
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let (matrix, inverse, normal_matrix) = match &self.motion {
+            Some(motion) => {
+                let matrix = motion.at(r.time);
+                let inverse = matrix.inverse()?;
+                (matrix, inverse, inverse.transpose())
+            }
+            None => (self.matrix, self.inverse, self.normal_matrix),
+        };
+
+        // The direction isn't normalized, so `t` means the same in both spaces
+        let local_r = Ray::new(
+            inverse.transform_point(&r.origin),
+            inverse.transform_vector(&r.direction),
+        )
+        .with_time(r.time);
+
+        let mut hit = self.object.hit(&local_r, ray_t)?;
+        hit.p = matrix.transform_point(&hit.p);
+        hit.normal = normal_matrix.transform_vector(&hit.normal).normalize();
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+    // Moving instances aren't sampled as lights, as the sampled direction would depend on time
+    fn is_emissive(&self) -> bool {
+        self.motion.is_none() && self.object.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> FP {
+        let local_direction = self.inverse.transform_vector(direction).normalize();
+        let pdf = self
+            .object
+            .pdf_value(&self.inverse.transform_point(origin), &local_direction);
+
+        // Solid angles get stretched by |det M| / |M d|³ going from object to world space
+        let stretch = self
+            .matrix
+            .transform_vector(&local_direction)
+            .length()
+            .powi(3)
+            / self.matrix.determinant3().abs();
+        pdf * stretch
+    }
+
+    fn random(&self, origin: &Point3, u: (FP, FP)) -> Vec3 {
+        let local_origin = self.inverse.transform_point(origin);
+        self.matrix
+            .transform_vector(&self.object.random(&local_origin, u))
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if self.motion.is_some() {
+            return;
+        }
+        let mut object_lights = HittableList::default();
+        self.object.collect_lights(&mut object_lights);
+        for light in object_lights.objects {
+            lights.add(Arc::new(self.with_object(light)));
+        }
+    }
 }
 
 pub struct Translate {
@@ -108,10 +329,31 @@ impl Hittable for Translate {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    fn is_emissive(&self) -> bool {
+        self.object.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> FP {
+        self.object.pdf_value(&(*origin - self.offset), direction)
+    }
+
+    fn random(&self, origin: &Point3, u: (FP, FP)) -> Vec3 {
+        self.object.random(&(*origin - self.offset), u)
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut object_lights = HittableList::default();
+        self.object.collect_lights(&mut object_lights);
+        for light in object_lights.objects {
+            lights.add(Arc::new(Translate::new(light, self.offset)));
+        }
+    }
 }
 
 pub struct RotateY {
     object: Arc<dyn Hittable>,
+    angle: FP,
     sin_theta: FP,
     cos_theta: FP,
     bbox: AABB,
@@ -150,11 +392,28 @@ impl RotateY {
 
         Self {
             object,
+            angle,
             sin_theta,
             cos_theta,
             bbox,
         }
     }
+
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
 }
 impl Hittable for RotateY {
     fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord> {
@@ -190,4 +449,127 @@ impl Hittable for RotateY {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    fn is_emissive(&self) -> bool {
+        self.object.is_emissive()
+    }
+
+    // Rotations keep solid angles as they are
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> FP {
+        self.object
+            .pdf_value(&self.to_object(origin), &self.to_object(direction))
+    }
+
+    fn random(&self, origin: &Point3, u: (FP, FP)) -> Vec3 {
+        self.to_world(&self.object.random(&self.to_object(origin), u))
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut object_lights = HittableList::default();
+        self.object.collect_lights(&mut object_lights);
+        for light in object_lights.objects {
+            lights.add(Arc::new(RotateY::new(light, self.angle)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bvh::BVHNode,
+        material::{DiffuseLight, Lambertian},
+        quad::Quad,
+        texture::SolidColor,
+    };
+
+    fn light() -> Arc<dyn Material> {
+        Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(4.0, 4.0, 4.0))))
+    }
+
+    fn white() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new(0.7, 0.7, 0.7))))
+    }
+
+    fn unit_quad(mat: Arc<dyn Material>) -> Arc<dyn Hittable> {
+        Arc::new(Quad::new(
+            Point3::ZERO,
+            Vec3::RIGHT,
+            Vec3::new(0.0, 0.0, 1.0),
+            mat,
+        ))
+    }
+
+    /// Checks that directions sampled from `placed` have the pdf `reference` gives them.
+    fn assert_same_light(placed: &dyn Hittable, reference: &dyn Hittable) {
+        let origins = [Point3::new(0.3, 0.0, 0.2), Point3::new(-2.0, 5.0, 1.0)];
+        for origin in origins {
+            for i in 0..16 {
+                let u = ((i as FP + 0.5) / 16.0, ((i * 7 % 16) as FP + 0.5) / 16.0);
+                let direction = placed.random(&origin, u);
+                let (a, b) = (
+                    placed.pdf_value(&origin, &direction),
+                    reference.pdf_value(&origin, &direction),
+                );
+                assert!(b > 0.0, "sampled a direction missing the light");
+                assert!((a - b).abs() < 1e-9 * b, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn lights_are_found_inside_groups_and_instances() {
+        let mut bvh_objects = HittableList::default();
+        bvh_objects.add(unit_quad(white()));
+        bvh_objects.add(unit_quad(light()));
+        let mut group = HittableList::default();
+        group.add(Arc::new(BVHNode::new(&mut bvh_objects)));
+        group.add(Arc::new(RotateY::new(
+            Arc::new(Translate::new(unit_quad(light()), Vec3::UP)),
+            30.0,
+        )));
+
+        let mut world = HittableList::default();
+        world.add(unit_quad(white()));
+        world.add(Arc::new(group));
+        world.add(unit_quad(light()));
+
+        assert_eq!(world.with_ids().lights().objects.len(), 3);
+    }
+
+    #[test]
+    fn instanced_lights_sample_like_the_placed_object() {
+        let placed = RotateY::new(
+            Arc::new(Translate::new(unit_quad(light()), Vec3::new(0.0, 2.0, 0.0))),
+            90.0,
+        );
+        let reference = Quad::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::RIGHT,
+            light(),
+        );
+
+        assert_same_light(&placed, &reference);
+    }
+
+    #[test]
+    fn transformed_lights_sample_like_the_placed_object() {
+        let matrix = Mat4::translation(Vec3::new(0.0, 2.0, 0.0))
+            * Mat4::rotation(Vec3::UP, 90.0)
+            * Mat4::scaling(Vec3::new(2.0, 1.0, 3.0));
+        let placed = Transform::new(unit_quad(light()), matrix).expect("Should've been invertible");
+        let reference = Quad::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, 0.0, -2.0),
+            Vec3::new(3.0, 0.0, 0.0),
+            light(),
+        );
+
+        assert_same_light(&placed, &reference);
+        let moving = placed
+            .with_end(Mat4::IDENTITY)
+            .expect("Should've been invertible");
+        assert!(!moving.is_emissive());
+    }
 }
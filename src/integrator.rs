@@ -0,0 +1,478 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use crate::{
+    bvh,
+    camera::Camera,
+    color::heat_color,
+    common::FP,
+    environment::Environment,
+    hittable::{HitRecord, Hittable, HittableList},
+    interval::Interval,
+    material::ScatterKind,
+    onb::ONB,
+    pdf::{HittablePDF, PDF},
+    ray::Ray,
+    sampler::Sampler,
+    vec3::{Color, Point3, Vec3},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IntegratorType {
+    /// Path tracing with next event estimation
+    #[default]
+    Path,
+    /// Direct light only, following mirrors and glass
+    Whitted,
+    /// Shading normal of the first hit, mapped to 0.5 * n + 0.5
+    Normal,
+    /// Texture coordinates of the first hit in red and green
+    Uv,
+    /// Distance to the first hit relative to the farthest corner of the scene
+    Depth,
+    /// Fraction of the hemisphere that's open up to a tenth of the scene size
+    AmbientOcclusion,
+    /// Bounding box and object tests of the camera rays as a heatmap
+    BvhCost,
+    /// Surface color of the first hit
+    Albedo,
+}
+
+impl IntegratorType {
+    /// Whether it outputs radiance to be tone mapped, instead of a visualization.
+    pub fn is_radiance(&self) -> bool {
+        matches!(self, IntegratorType::Path | IntegratorType::Whitted)
+    }
+
+    pub fn build(&self, camera: &Camera, world: &dyn Hittable) -> Box<dyn Integrator> {
+        let bbox = world.bounding_box();
+        let scene_size = Vec3::new(bbox.x.size(), bbox.y.size(), bbox.z.size()).length();
+        let scene_size = if scene_size.is_finite() && scene_size > 0.0 {
+            scene_size
+        } else {
+            1.0
+        };
+        let max_depth = (0..8)
+            .map(|corner| {
+                let axis = |n: usize| {
+                    let interval = bbox.axis(n);
+                    if corner & (1 << n) == 0 {
+                        interval.min
+                    } else {
+                        interval.max
+                    }
+                };
+                (Vec3::new(axis(0), axis(1), axis(2)) - camera.center()).length()
+            })
+            .fold(0.0, FP::max);
+        let max_depth = if max_depth.is_finite() && max_depth > 0.0 {
+            max_depth
+        } else {
+            1.0
+        };
+
+        match self {
+            IntegratorType::Path => Box::new(PathIntegrator {
+                max_depth: camera.max_depth,
+                roulette_depth: camera.roulette_depth,
+                environment: Arc::clone(&camera.environment),
+            }),
+            IntegratorType::Whitted => Box::new(WhittedIntegrator {
+                max_depth: camera.max_depth,
+                environment: Arc::clone(&camera.environment),
+            }),
+            IntegratorType::Normal => Box::new(NormalIntegrator),
+            IntegratorType::Uv => Box::new(UVIntegrator),
+            IntegratorType::Depth => Box::new(DepthIntegrator { max_depth }),
+            IntegratorType::AmbientOcclusion => Box::new(AmbientOcclusionIntegrator {
+                distance: 0.1 * scene_size,
+            }),
+            IntegratorType::BvhCost => Box::new(BVHCostIntegrator),
+            IntegratorType::Albedo => Box::new(AlbedoIntegrator {
+                environment: Arc::clone(&camera.environment),
+            }),
+        }
+    }
+}
+
+/// Computes the color seen along a camera ray.
+pub trait Integrator: Sync + Send {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color;
+}
+
+fn first_hit<'a>(ray: &Ray, world: &'a dyn Hittable) -> Option<HitRecord<'a>> {
+    world.hit(ray, &Interval::new(0.001, FP::INFINITY))
+}
+
+/// Balances light and BSDF sampling by weighting the sample drawn with density `pdf`
+/// against the density `other_pdf` the other strategy would have produced it with.
+fn power_heuristic(pdf: FP, other_pdf: FP) -> FP {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// Chance of next event estimation sampling the environment instead of the lights.
+fn environment_weight(lights: &HittableList, environment: &dyn Environment) -> FP {
+    match (lights.is_empty(), environment.is_sampled()) {
+        (_, false) => 0.0,
+        (true, true) => 1.0,
+        (false, true) => 0.5,
+    }
+}
+
+/// Density of next event estimation sampling `direction` from `origin`, counting both the
+/// lights and the environment.
+fn light_pdf_value(
+    lights: &HittableList,
+    environment: &dyn Environment,
+    origin: &Point3,
+    direction: &Vec3,
+) -> FP {
+    let weight = environment_weight(lights, environment);
+    let mut pdf = 0.0;
+    if weight > 0.0 {
+        pdf += weight * environment.pdf_value(direction);
+    }
+    if weight < 1.0 && !lights.is_empty() {
+        pdf += (1.0 - weight) * lights.pdf_value(origin, direction);
+    }
+    pdf
+}
+
+/// Next event estimation: connects `hit` to a point on a light or a direction of the
+/// environment drawn with `u`. Returns the ray towards the light, the light reaching the hit
+/// through it weighted by the scattering density over the light density, and the light
+/// density.
+fn sample_light(
+    ray: &Ray,
+    hit: &HitRecord,
+    world: &dyn Hittable,
+    lights: &HittableList,
+    environment: &dyn Environment,
+    (u1, u2): (FP, FP),
+) -> Option<(Ray, Color, FP)> {
+    let weight = environment_weight(lights, environment);
+    if lights.is_empty() && weight == 0.0 {
+        return None;
+    }
+
+    // The first dimension picks the environment or the lights and is stretched back to [0, 1)
+    let direction = if u1 < weight {
+        environment.sample((u1 / weight, u2))
+    } else {
+        HittablePDF::new(lights, hit.p).generate(((u1 - weight) / (1.0 - weight), u2))
+    };
+    let light_ray = Ray::new(hit.p, direction).with_time(ray.time);
+    let light_pdf_value = light_pdf_value(lights, environment, &hit.p, &direction);
+    let scattering_pdf = hit.mat.scattering_pdf(ray, hit, &light_ray);
+    if light_pdf_value <= 0.0 || scattering_pdf <= 0.0 {
+        return None;
+    }
+
+    let light = match first_hit(&light_ray, world) {
+        Some(light_hit) => light_hit
+            .mat
+            .emitted(light_hit.u, light_hit.v, &light_hit.p),
+        // An environment that isn't sampled is only picked up by the BSDF bounce
+        None if environment.is_sampled() => environment.radiance(&direction),
+        None => return None,
+    };
+    Some((
+        light_ray,
+        light * scattering_pdf / light_pdf_value,
+        light_pdf_value,
+    ))
+}
+
+/// Path tracing with next event estimation, ending paths by Russian roulette from
+/// `roulette_depth` bounces on.
+pub struct PathIntegrator {
+    max_depth: i32,
+    roulette_depth: i32,
+    environment: Arc<dyn Environment>,
+}
+
+impl PathIntegrator {
+    fn trace(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let mut color = Color::ZERO;
+        let mut throughput = Color::ONE;
+        let mut ray = Ray::new(ray.origin, ray.direction).with_time(ray.time);
+        // Density the last bounce sampled `ray` with, `None` for camera rays and specular
+        // bounces where light sampling could not have found the same path
+        let mut bsdf_pdf: Option<FP> = None;
+
+        let environment = self.environment.as_ref();
+        // Light found by a bounce, weighted against next event estimation finding it as well
+        let weighted = |light: Color, ray: &Ray, bsdf_pdf: Option<FP>| match bsdf_pdf {
+            Some(bsdf_pdf) if !light.near_zero() => {
+                let light_pdf = light_pdf_value(lights, environment, &ray.origin, &ray.direction);
+                light * power_heuristic(bsdf_pdf, light_pdf)
+            }
+            _ => light,
+        };
+
+        for depth in 0..self.max_depth {
+            let Some(hit) = first_hit(&ray, world) else {
+                let radiance = environment.radiance(&ray.direction);
+                if environment.is_sampled() {
+                    color += throughput * weighted(radiance, &ray, bsdf_pdf);
+                } else {
+                    color += throughput * radiance;
+                }
+                break;
+            };
+
+            let color_from_emission = hit.mat.emitted(hit.u, hit.v, &hit.p);
+            color += throughput * weighted(color_from_emission, &ray, bsdf_pdf);
+
+            let (u1, u2) = sampler.get_2d();
+            let Some(srec) = hit.mat.scatter(&ray, &hit, (u1, u2, sampler.get_1d())) else {
+                break;
+            };
+
+            let scattered = match srec.kind {
+                ScatterKind::Specular(scattered) => {
+                    throughput *= srec.attenuation;
+                    bsdf_pdf = None;
+                    scattered
+                }
+                ScatterKind::PDF(bsdf) => {
+                    let light_sample = sampler.get_2d();
+                    let bsdf_sample = sampler.get_2d();
+
+                    if let Some((light_ray, light, light_pdf)) =
+                        sample_light(&ray, &hit, world, lights, environment, light_sample)
+                    {
+                        let weight = power_heuristic(light_pdf, bsdf.value(&light_ray.direction));
+                        color += throughput * srec.attenuation * light * weight;
+                    }
+
+                    let scattered = Ray::new(hit.p, bsdf.generate(bsdf_sample)).with_time(ray.time);
+                    let pdf_value = bsdf.value(&scattered.direction);
+                    if pdf_value <= 0.0 {
+                        break;
+                    }
+                    let scattering_pdf = hit.mat.scattering_pdf(&ray, &hit, &scattered);
+                    throughput *= srec.attenuation * scattering_pdf / pdf_value;
+                    bsdf_pdf = Some(pdf_value);
+                    scattered
+                }
+            };
+
+            // Russian roulette: end dim paths at random and boost the survivors to make up for it
+            if depth + 1 >= self.roulette_depth {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+            ray = scattered;
+        }
+
+        color
+    }
+}
+
+impl Integrator for PathIntegrator {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        self.trace(ray, world, lights, sampler)
+    }
+}
+
+/// Light arriving straight from the light sources, with perfect reflections and refractions.
+pub struct WhittedIntegrator {
+    max_depth: i32,
+    environment: Arc<dyn Environment>,
+}
+
+impl WhittedIntegrator {
+    fn trace(
+        &self,
+        ray: &Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        if depth <= 0 {
+            return Color::ZERO;
+        }
+
+        let Some(hit) = first_hit(ray, world) else {
+            return self.environment.radiance(&ray.direction);
+        };
+
+        let color_from_emission = hit.mat.emitted(hit.u, hit.v, &hit.p);
+        let (u1, u2) = sampler.get_2d();
+        let Some(srec) = hit.mat.scatter(ray, &hit, (u1, u2, sampler.get_1d())) else {
+            return color_from_emission;
+        };
+
+        match srec.kind {
+            ScatterKind::Specular(scattered) => {
+                color_from_emission
+                    + srec.attenuation * self.trace(&scattered, depth - 1, world, lights, sampler)
+            }
+            ScatterKind::PDF(_) => {
+                let light_sample = sampler.get_2d();
+                let environment = self.environment.as_ref();
+                match sample_light(ray, &hit, world, lights, environment, light_sample) {
+                    Some((_, light, _)) => color_from_emission + srec.attenuation * light,
+                    None => color_from_emission,
+                }
+            }
+        }
+    }
+}
+
+impl Integrator for WhittedIntegrator {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        self.trace(ray, self.max_depth, world, lights, sampler)
+    }
+}
+
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _lights: &HittableList,
+        _sampler: &mut dyn Sampler,
+    ) -> Color {
+        match first_hit(ray, world) {
+            Some(hit) => 0.5 * (hit.normal + Vec3::ONE),
+            None => Color::ZERO,
+        }
+    }
+}
+
+pub struct UVIntegrator;
+
+impl Integrator for UVIntegrator {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _lights: &HittableList,
+        _sampler: &mut dyn Sampler,
+    ) -> Color {
+        match first_hit(ray, world) {
+            Some(hit) => Color::new(hit.u, hit.v, 0.0),
+            None => Color::ZERO,
+        }
+    }
+}
+
+pub struct DepthIntegrator {
+    max_depth: FP,
+}
+
+impl Integrator for DepthIntegrator {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _lights: &HittableList,
+        _sampler: &mut dyn Sampler,
+    ) -> Color {
+        match first_hit(ray, world) {
+            Some(hit) => Color::splat(hit.t * ray.direction.length() / self.max_depth),
+            None => Color::ZERO,
+        }
+    }
+}
+
+pub struct AmbientOcclusionIntegrator {
+    distance: FP,
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _lights: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let Some(hit) = first_hit(ray, world) else {
+            return Color::ONE;
+        };
+
+        let direction =
+            ONB::new(&hit.normal).local(&Vec3::random_cosine_direction(sampler.get_2d()));
+        let occlusion_ray = Ray::new(hit.p, direction).with_time(ray.time);
+        match world.hit(&occlusion_ray, &Interval::new(0.001, self.distance)) {
+            Some(_) => Color::ZERO,
+            None => Color::ONE,
+        }
+    }
+}
+
+/// Colors the camera rays by their BVH work on a log scale, white at 1023 tests.
+pub struct BVHCostIntegrator;
+
+impl Integrator for BVHCostIntegrator {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _lights: &HittableList,
+        _sampler: &mut dyn Sampler,
+    ) -> Color {
+        bvh::take_traversal_steps();
+        first_hit(ray, world);
+        let steps = bvh::take_traversal_steps() as FP;
+        heat_color((1.0 + steps).log2() / 10.0)
+    }
+}
+
+pub struct AlbedoIntegrator {
+    environment: Arc<dyn Environment>,
+}
+
+impl Integrator for AlbedoIntegrator {
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _lights: &HittableList,
+        _sampler: &mut dyn Sampler,
+    ) -> Color {
+        match first_hit(ray, world) {
+            Some(hit) => hit.mat.albedo(&hit),
+            None => self.environment.radiance(&ray.direction),
+        }
+    }
+}
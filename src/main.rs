@@ -6,38 +6,75 @@ use crate::{
     texture::{CheckerTexture, ImageTexture, SolidColor},
     vec3::Point3,
 };
-use bvh::BVHNode;
+use animation::parse_frames;
+use aov::AOV;
+use bvh::{BVHBuilder, BVHNode};
 use camera::CameraSettings;
+use checkpoint::CheckpointSettings;
 use clap::Parser;
+use color::{ToneMapper, ToneMapping};
 use common::FP;
 use constant_medium::ConstantMedium;
+use filter::{Filter, FilterType};
 use hittable::{Hittable, RotateY, Translate};
+use integrator::IntegratorType;
 use material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use output::{frame_path, OutputFormat};
 use quad::Quad;
-use rand::Rng;
-use renderer::render;
-use std::{sync::Arc, time::Instant};
+use reload::SceneWatcher;
+use renderer::{
+    render, render_stopped, stop_render, AdaptiveSampling, RenderOutput, RenderSettings,
+};
+use sampler::SamplerType;
+use scene::SceneAnimation;
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 use texture::{NoiseTexture, Texture};
 use vec3::{Color, Vec3};
 
 mod aabb;
+mod animation;
+mod aov;
 mod bvh;
 mod camera;
+mod checkpoint;
 mod color;
 mod common;
 mod constant_medium;
+mod controls;
+mod environment;
+mod filter;
 mod hittable;
+mod integrator;
 mod interval;
 mod material;
+mod matrix;
+mod mesh;
+mod onb;
+mod output;
+mod pdf;
 mod perlin;
 mod quad;
 mod ray;
+mod reload;
 mod renderer;
+mod sampler;
+mod scene;
+mod sky;
 mod sphere;
 mod texture;
+mod triangle;
 mod vec3;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Enable live rendering
@@ -48,9 +85,104 @@ struct Args {
     #[arg(short, long, default_value_t = 0)]
     scene: i32,
 
-    /// Name of the output file that the render will end up in
+    /// Loads the scene from a TOML scene file instead of using a built-in scene
+    #[arg(long)]
+    scene_file: Option<String>,
+
+    /// Algorithm used to build the scene BVH
+    #[arg(long, value_enum, default_value_t = BVHBuilder::default())]
+    bvh: BVHBuilder,
+
+    /// Name of the output file that the render will end up in, its extension picks the format
     #[arg(short, long, default_value = "output")]
     output: String,
+
+    /// Image format of the output file, overriding the output file extension
+    #[arg(short, long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Extra passes written beside the output file, e.g. `--aov albedo,normal`
+    #[arg(long, value_enum, value_delimiter = ',')]
+    aov: Vec<AOV>,
+
+    /// Exposure compensation in stops, overriding the scene
+    #[arg(long, allow_negative_numbers = true)]
+    exposure: Option<FP>,
+
+    /// Tone mapping operator used for PNG and live output, overriding the scene
+    #[arg(long, value_enum)]
+    tone_mapper: Option<ToneMapper>,
+
+    /// Seed of the random numbers used for the scene and the render, random if not set
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Generator of the pixel, lens, time and bounce samples
+    #[arg(long, value_enum, default_value_t = SamplerType::default())]
+    sampler: SamplerType,
+
+    /// Path tracer, direct light only or a debug view of the scene
+    #[arg(long, value_enum, default_value_t = IntegratorType::default())]
+    integrator: IntegratorType,
+
+    /// Bounces after which a path is always cut off, overriding the scene
+    #[arg(long)]
+    max_depth: Option<i32>,
+
+    /// Bounces before Russian roulette may end a path, overriding the scene
+    #[arg(long)]
+    roulette_depth: Option<i32>,
+
+    /// Reconstruction filter weighting the samples of a pixel and its neighbours
+    #[arg(long, value_enum, default_value_t = FilterType::default())]
+    filter: FilterType,
+
+    /// Filter radius in pixels, defaults to 0.5 for box, 1 for tent, 1.5 for gaussian,
+    /// 2 for mitchell and 3 for lanczos
+    #[arg(long)]
+    filter_radius: Option<FP>,
+
+    /// Stops sampling a pixel once its relative standard error falls below this, e.g. 0.01
+    #[arg(long)]
+    adaptive_threshold: Option<FP>,
+
+    /// Samples every pixel gets before adaptive sampling may stop it
+    #[arg(long, default_value_t = 16)]
+    min_spp: i32,
+
+    /// Samples per pixel, overriding the scene
+    #[arg(long, visible_alias = "target-spp")]
+    spp: Option<i32>,
+
+    /// Stops rendering after this long, e.g. `90s` or `10m`, sampling until then unless
+    /// `--spp` is given as well
+    #[arg(long, value_parser = humantime::parse_duration)]
+    time_limit: Option<Duration>,
+
+    /// Samples added to every pixel per pass, checkpoints are written between passes
+    #[arg(long, default_value_t = 16)]
+    pass_spp: i32,
+
+    /// Periodically saves the accumulated samples to a `.checkpoint` file beside the output
+    #[arg(long)]
+    checkpoint: bool,
+
+    /// Seconds between checkpoints
+    #[arg(long, default_value_t = 60)]
+    checkpoint_interval: u64,
+
+    /// Continues the render in the checkpoint file, e.g. to add samples after raising the spp
+    #[arg(long)]
+    resume: bool,
+
+    /// Renders the frames `start..end` of the scene file's animation to numbered output files,
+    /// e.g. `0..120`
+    #[arg(long, value_parser = parse_frames, requires = "scene_file", conflicts_with = "live")]
+    frames: Option<Range<u32>>,
+
+    /// Frames per second of the animation
+    #[arg(long, default_value_t = 24.0)]
+    fps: FP,
 }
 
 fn random_balls() -> (HittableList, Camera) {
@@ -67,12 +199,12 @@ fn random_balls() -> (HittableList, Camera) {
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = rand::random::<FP>();
+            let choose_mat = common::random::<FP>();
 
             let center = Point3::new(
-                a as FP + 0.9 * rand::random::<FP>(),
+                a as FP + 0.9 * common::random::<FP>(),
                 0.2,
-                b as FP + 0.9 * rand::random::<FP>(),
+                b as FP + 0.9 * common::random::<FP>(),
             );
 
             if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
@@ -80,7 +212,7 @@ fn random_balls() -> (HittableList, Camera) {
                     let color = Arc::new(SolidColor::from(Color::random() * Color::random()));
                     world.add(Arc::new(
                         Sphere::new(center, 0.2, Arc::new(Lambertian::new(color)))
-                            .with_target(center + Vec3::UP * rand::random::<FP>() * 0.5),
+                            .with_target(center + Vec3::UP * common::random::<FP>() * 0.5),
                     ));
                 } else if choose_mat < 0.95 {
                     world.add(Arc::new(Sphere::new(
@@ -88,7 +220,7 @@ fn random_balls() -> (HittableList, Camera) {
                         0.2,
                         Arc::new(Metal::new(
                             Color::random_range(0.5, 1.0),
-                            rand::thread_rng().gen_range(0.0..0.5),
+                            common::random_range(0.0..0.5),
                         )),
                     )));
                 } else {
@@ -122,7 +254,7 @@ fn random_balls() -> (HittableList, Camera) {
         aspect_ratio: 16.0 / 9.0,
         image_width: 600,
         samples_per_pixel: 128,
-        max_depth: 8,
+        max_depth: 64,
         background: Color::new(0.7, 0.8, 1.0),
 
         vfov: 20.0,
@@ -159,7 +291,7 @@ fn two_spheres() -> (HittableList, Camera) {
         aspect_ratio: 16.0 / 9.0,
         image_width: 1200,
         samples_per_pixel: 128,
-        max_depth: 8,
+        max_depth: 64,
         background: Color::new(0.7, 0.8, 1.0),
 
         vfov: 20.0,
@@ -189,7 +321,7 @@ fn earth() -> (HittableList, Camera) {
         aspect_ratio: 16.0 / 9.0,
         image_width: 1200,
         samples_per_pixel: 128,
-        max_depth: 8,
+        max_depth: 64,
         background: Color::new(0.7, 0.8, 1.0),
 
         vfov: 20.0,
@@ -223,7 +355,7 @@ fn two_perlin_spheres() -> (HittableList, Camera) {
         aspect_ratio: 16.0 / 9.0,
         image_width: 1200,
         samples_per_pixel: 128,
-        max_depth: 8,
+        max_depth: 64,
         background: Color::new(0.7, 0.8, 1.0),
 
         vfov: 20.0,
@@ -280,7 +412,7 @@ fn quads() -> (HittableList, Camera) {
         aspect_ratio: 1.0,
         image_width: 1200,
         samples_per_pixel: 128,
-        max_depth: 8,
+        max_depth: 64,
         background: Color::new(0.7, 0.8, 1.0),
 
         vfov: 80.0,
@@ -328,7 +460,7 @@ fn simple_light() -> (HittableList, Camera) {
         aspect_ratio: 16.0 / 9.0,
         image_width: 600,
         samples_per_pixel: 1024,
-        max_depth: 8,
+        max_depth: 64,
         background: Color::ZERO,
 
         vfov: 20.0,
@@ -407,7 +539,7 @@ fn cornell_box() -> (HittableList, Camera) {
         aspect_ratio: 1.0,
         image_width: 600,
         samples_per_pixel: 4096,
-        max_depth: 8,
+        max_depth: 64,
         background: Color::ZERO,
 
         vfov: 40.0,
@@ -492,7 +624,7 @@ fn cornell_smoke() -> (HittableList, Camera) {
         aspect_ratio: 1.0,
         image_width: 600,
         samples_per_pixel: 4096,
-        max_depth: 8,
+        max_depth: 64,
         background: Color::ZERO,
 
         vfov: 40.0,
@@ -520,7 +652,7 @@ fn final_scene() -> (HittableList, Camera) {
             let z0 = -1000.0 + j as FP * side;
             let z1 = z0 + side;
             let y0 = 0.0;
-            let y1 = rand::thread_rng().gen_range(1.0..101.0);
+            let y1 = common::random_range(1.0..101.0);
             boxes1.add(Quad::cube(
                 &Point3::new(x0, y0, z0),
                 &Point3::new(x1, y1, z1),
@@ -625,7 +757,7 @@ fn final_scene() -> (HittableList, Camera) {
         aspect_ratio: 1.0,
         image_width: 800,
         samples_per_pixel: 8192,
-        max_depth: 40,
+        max_depth: 64,
         background: Color::ZERO,
 
         vfov: 40.0,
@@ -638,31 +770,285 @@ fn final_scene() -> (HittableList, Camera) {
     (world, camera)
 }
 
+/// Applies the command line overrides to the render settings of the scene.
+fn apply_overrides(args: &Args, camera: &mut Camera) {
+    if let Some(spp) = args.spp {
+        camera.samples_per_pixel = spp;
+    } else if args.time_limit.is_some() && !args.live {
+        camera.samples_per_pixel = i32::MAX;
+    }
+    if let Some(max_depth) = args.max_depth {
+        camera.max_depth = max_depth;
+    }
+    if let Some(roulette_depth) = args.roulette_depth {
+        camera.roulette_depth = roulette_depth;
+    }
+    // Debug views are shown as they are unless asked otherwise
+    if !args.integrator.is_radiance() {
+        camera.tone_mapping = ToneMapping::default();
+    }
+    if let Some(exposure) = args.exposure {
+        camera.tone_mapping.exposure = exposure;
+    }
+    if let Some(tone_mapper) = args.tone_mapper {
+        camera.tone_mapping.tone_mapper = tone_mapper;
+    }
+}
+
+fn render_settings(args: &Args, seed: u64) -> RenderSettings {
+    RenderSettings {
+        seed,
+        sampler: args.sampler,
+        integrator: args.integrator,
+        adaptive: args.adaptive_threshold.map(|threshold| AdaptiveSampling {
+            threshold,
+            min_samples: args.min_spp,
+        }),
+        pass_samples: args.pass_spp,
+        time_limit: args.time_limit,
+        filter: Filter::new(args.filter, args.filter_radius),
+    }
+}
+
+/// Checkpoints of the render to `output_path`, if asked for. Frames of an animation resume
+/// only when they have a checkpoint, the ones after an interrupted frame start from scratch.
+fn checkpoint_settings(
+    args: &Args,
+    camera: &Camera,
+    settings: &RenderSettings,
+    output_path: &Path,
+    frame: Option<u32>,
+    files: &[PathBuf],
+) -> std::io::Result<Option<CheckpointSettings>> {
+    if !args.checkpoint && !args.resume {
+        return Ok(None);
+    }
+    let path = output_path.with_extension("checkpoint");
+    let mut scene = match &args.scene_file {
+        Some(scene_file) => fs::read_to_string(scene_file)?,
+        None => format!("scene {}", args.scene),
+    };
+    // Edited textures, meshes and environment maps change the samples just as much
+    let mut files = files.to_vec();
+    files.sort();
+    files.dedup();
+    for file in files {
+        let stamp = fs::metadata(&file).and_then(|m| Ok((m.len(), m.modified()?)));
+        scene = format!("{}\n{} {:?}", scene, file.display(), stamp.ok());
+    }
+    if let Some(frame) = frame {
+        scene = format!("{}\nframe {} at {} fps", scene, frame, args.fps);
+    }
+    let description = format!(
+        "{}\n{}x{} {} {} {} {:?} {:?} {:?} {:?}",
+        scene,
+        camera.image_width,
+        camera.image_height,
+        camera.max_depth,
+        camera.roulette_depth,
+        settings.seed,
+        args.sampler,
+        args.integrator,
+        settings.filter,
+        args.aov
+    );
+    Ok(Some(CheckpointSettings {
+        resume: args.resume && (frame.is_none() || path.exists()),
+        path,
+        interval: Duration::from_secs(args.checkpoint_interval),
+        fingerprint: checkpoint::fingerprint(&description),
+    }))
+}
+
+/// The first Ctrl-C writes the image rendered so far, the second one exits right away.
+fn set_stop_handler() {
+    ctrlc::set_handler(|| {
+        static PRESSED: AtomicBool = AtomicBool::new(false);
+        if PRESSED.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        stop_render();
+    })
+    .expect("Should've set the Ctrl-C handler.");
+}
+
+/// Renders `frames` of the scene file's animation, each to `output_path` numbered with the
+/// frame. Stopping a frame early ends the sequence after writing it.
+fn render_sequence(
+    args: &Args,
+    frames: Range<u32>,
+    seed: u64,
+    output_path: &Path,
+    output_format: OutputFormat,
+) -> std::io::Result<()> {
+    let scene_file = args
+        .scene_file
+        .as_deref()
+        .expect("Should've required a scene file for the frames.");
+    let mut animation = match SceneAnimation::load(scene_file, args.bvh) {
+        Ok(animation) => animation,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let settings = render_settings(args, seed);
+    set_stop_handler();
+
+    for frame in frames {
+        let time = frame as FP / args.fps;
+        println!("Frame {} at {:.3}s", frame, time);
+
+        // Objects rebuilt for the frame draw the same random numbers every time
+        common::seed_rng(seed, u64::MAX, 0);
+        let now = Instant::now();
+        let mut scene = match animation.frame(time, 1.0 / args.fps) {
+            Ok(scene) => scene,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
+        println!("Building scene: {:.2?}", now.elapsed());
+        apply_overrides(args, &mut scene.camera);
+
+        let path = frame_path(output_path, frame);
+        let output = RenderOutput {
+            checkpoint: checkpoint_settings(
+                args,
+                &scene.camera,
+                &settings,
+                &path,
+                Some(frame),
+                animation.files(),
+            )?,
+            path,
+            format: output_format,
+            aovs: args.aov.clone(),
+        };
+        if let Err(err) = render(
+            Arc::new(scene.camera),
+            scene.world,
+            scene.lights,
+            settings,
+            &output,
+        ) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        if render_stopped() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
     println!("Args: {:?}", args);
 
-    let (mut world, camera) = match args.scene {
-        0 => random_balls(),
-        1 => two_spheres(),
-        2 => earth(),
-        3 => two_perlin_spheres(),
-        4 => quads(),
-        5 => simple_light(),
-        6 => cornell_box(),
-        7 => cornell_smoke(),
-        8 => final_scene(),
-        _ => random_balls(),
+    let (output_path, output_format) = OutputFormat::resolve(&args.output, args.format);
+    // A sequence keeps the seed in the checkpoints of all its frames
+    let checkpoint_path = match &args.frames {
+        Some(frames) => frame_path(&output_path, frames.start),
+        None => output_path.clone(),
+    }
+    .with_extension("checkpoint");
+
+    let seed = match args.seed {
+        Some(seed) => seed,
+        None if args.resume => match checkpoint::read_seed(&checkpoint_path) {
+            Ok(seed) => seed,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        None => rand::random(),
+    };
+    println!("Seed: {}", seed);
+    // Procedural scenes, Perlin noise and the BVH draw from the generator as well
+    common::seed_rng(seed, u64::MAX, 0);
+
+    if let Some(frames) = args.frames.clone() {
+        return render_sequence(&args, frames, seed, &output_path, output_format);
+    }
+
+    let mut scene_files = Vec::new();
+    let (mut world, mut camera) = if let Some(scene_file) = &args.scene_file {
+        match scene::load(scene_file, args.bvh) {
+            Ok(scene) => {
+                scene_files = scene.files;
+                (scene.world, scene.camera)
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        let (world, camera) = match args.scene {
+            0 => random_balls(),
+            1 => two_spheres(),
+            2 => earth(),
+            3 => two_perlin_spheres(),
+            4 => quads(),
+            5 => simple_light(),
+            6 => cornell_box(),
+            7 => cornell_smoke(),
+            8 => final_scene(),
+            _ => random_balls(),
+        };
+        (world.with_ids(), camera)
+    };
+
+    apply_overrides(&args, &mut camera);
+
+    let lights = Arc::new(world.lights());
+
+    // A scene without objects shows just its background
+    let world: Arc<dyn Hittable> = if world.is_empty() {
+        Arc::new(world)
+    } else {
+        let now = Instant::now();
+        let bvh = BVHNode::new_with_builder(&mut world, args.bvh);
+        println!("Building BVH: {:.2?}", now.elapsed());
+        println!("BVH stats: {}", bvh.stats());
+        Arc::new(bvh)
     };
 
-    let now = Instant::now();
-    let bvh = BVHNode::new(&mut world);
-    println!("Building BVH: {:.2?}", now.elapsed());
+    let settings = render_settings(&args, seed);
 
     if args.live {
-        live_render(Arc::new(camera), Arc::new(bvh));
+        // Scene files are watched for changes, the built-in scenes would need a recompile
+        let watcher = args.scene_file.clone().map(|scene_file| {
+            let overrides = args.clone();
+            SceneWatcher::new(scene_file, scene_files, seed, args.bvh, move |camera| {
+                apply_overrides(&overrides, camera)
+            })
+        });
+        live_render(camera, world, lights, settings, watcher, output_path);
     } else {
-        render(Arc::new(camera), Arc::new(bvh), args.output);
+        set_stop_handler();
+
+        let output = RenderOutput {
+            checkpoint: checkpoint_settings(
+                &args,
+                &camera,
+                &settings,
+                &output_path,
+                None,
+                &scene_files,
+            )?,
+            path: output_path,
+            format: output_format,
+            aovs: args.aov.clone(),
+        };
+        if let Err(err) = render(Arc::new(camera), world, lights, settings, &output) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
     }
 
     Ok(())
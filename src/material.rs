@@ -1,18 +1,45 @@
 use std::sync::Arc;
 
 use crate::{
-    common::FP,
+    common::{FP, PI},
     hittable::HitRecord,
+    pdf::{CosinePDF, SpherePDF, PDF},
     ray::Ray,
     texture::Texture,
     vec3::{Color, Point3, Vec3},
 };
 
+pub enum ScatterKind {
+    /// A single deterministic (or perfectly sharp) direction that can't be importance
+    /// sampled from anywhere else.
+    Specular(Ray),
+    /// Directions to be drawn from the density, weighted by [`Material::scattering_pdf`].
+    PDF(Box<dyn PDF>),
+}
+
+pub struct ScatterRecord {
+    pub attenuation: Color,
+    pub kind: ScatterKind,
+}
+
 pub trait Material: Sync + Send {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Color)>;
+    /// The uniform sample `u` drives any random choice made while scattering, so the sampler
+    /// decides every bounce.
+    fn scatter(&self, ray: &Ray, hit: &HitRecord, u: (FP, FP, FP)) -> Option<ScatterRecord>;
     fn emitted(&self, _u: FP, _v: FP, _p: &Point3) -> Color {
         Color::ZERO
     }
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    /// Density of scattering into `scattered`. Only used for [`ScatterKind::PDF`] records.
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitRecord, _scattered: &Ray) -> FP {
+        0.0
+    }
+    /// Surface color at the hit, written to the albedo output pass.
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        Color::ZERO
+    }
 }
 
 pub struct Lambertian {
@@ -24,20 +51,20 @@ impl Lambertian {
     }
 }
 impl Material for Lambertian {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Color)> {
-        let scatter_direction = hit.normal + Vec3::random_unit_vector();
-        Some((
-            Ray::new(
-                hit.p,
-                if scatter_direction.near_zero() {
-                    hit.normal
-                } else {
-                    scatter_direction
-                },
-            )
-            .with_time(ray.time),
-            self.albedo.value(hit.u, hit.v, &hit.p),
-        ))
+    fn scatter(&self, _ray: &Ray, hit: &HitRecord, _u: (FP, FP, FP)) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            attenuation: self.albedo.value(hit.u, hit.v, &hit.p),
+            kind: ScatterKind::PDF(Box::new(CosinePDF::new(&hit.normal))),
+        })
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, hit: &HitRecord, scattered: &Ray) -> FP {
+        let cos_theta = hit.normal.dot(&scattered.direction.normalize());
+        (cos_theta / PI).max(0.0)
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.albedo.value(hit.u, hit.v, &hit.p)
     }
 }
 
@@ -51,16 +78,23 @@ impl Metal {
     }
 }
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord, u: (FP, FP, FP)) -> Option<ScatterRecord> {
         let reflected = ray.direction.normalize().reflect(&hit.normal)
-            + self.fuzz * Vec3::random_in_unit_sphere();
+            + self.fuzz * Vec3::random_in_unit_ball(u);
         if reflected.dot(&hit.normal) > 0.0 {
             let scattered = Ray::new(hit.p, reflected).with_time(ray.time);
-            Some((scattered, self.albedo))
+            Some(ScatterRecord {
+                attenuation: self.albedo,
+                kind: ScatterKind::Specular(scattered),
+            })
         } else {
             None
         }
     }
+
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 pub struct Dielectric {
@@ -78,7 +112,12 @@ impl Dielectric {
     }
 }
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit: &HitRecord,
+        (_, _, u3): (FP, FP, FP),
+    ) -> Option<ScatterRecord> {
         let attenuation = Color::ONE;
         let refraction_ratio = if hit.front_face {
             1.0 / self.ir
@@ -91,7 +130,7 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let direction = if refraction_ratio * sin_theta > 1.0
-            || Dielectric::reflectance(cos_theta, refraction_ratio) > rand::random()
+            || Dielectric::reflectance(cos_theta, refraction_ratio) > u3
         {
             unit_direction.reflect(&hit.normal)
         } else {
@@ -99,25 +138,44 @@ impl Material for Dielectric {
         };
 
         let scattered = Ray::new(hit.p, direction).with_time(ray.time);
-        Some((scattered, attenuation))
+        Some(ScatterRecord {
+            attenuation,
+            kind: ScatterKind::Specular(scattered),
+        })
+    }
+
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        Color::ONE
     }
 }
 
 pub struct DiffuseLight {
     emit: Arc<dyn Texture>,
+    intensity: FP,
 }
 impl DiffuseLight {
     pub fn new(emit: Arc<dyn Texture>) -> Self {
-        Self { emit }
+        Self {
+            emit,
+            intensity: 1.0,
+        }
+    }
+    /// Scales the emitted color by `intensity`.
+    pub fn with_intensity(self, intensity: FP) -> Self {
+        Self { intensity, ..self }
     }
 }
 impl Material for DiffuseLight {
-    fn scatter(&self, _ray: &Ray, _hit: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(&self, _ray: &Ray, _hit: &HitRecord, _u: (FP, FP, FP)) -> Option<ScatterRecord> {
         None
     }
 
     fn emitted(&self, u: FP, v: FP, p: &Point3) -> Color {
-        self.emit.value(u, v, p)
+        self.emit.value(u, v, p) * self.intensity
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
     }
 }
 
@@ -130,9 +188,18 @@ impl Isotropic {
     }
 }
 impl Material for Isotropic {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Color)> {
-        let scattered = Ray::new(hit.p, Vec3::random_unit_vector()).with_time(ray.time);
-        let attenuation = self.albedo.value(hit.u, hit.v, &hit.p);
-        Some((scattered, attenuation))
+    fn scatter(&self, _ray: &Ray, hit: &HitRecord, _u: (FP, FP, FP)) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            attenuation: self.albedo.value(hit.u, hit.v, &hit.p),
+            kind: ScatterKind::PDF(Box::new(SpherePDF)),
+        })
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitRecord, _scattered: &Ray) -> FP {
+        1.0 / (4.0 * PI)
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.albedo.value(hit.u, hit.v, &hit.p)
     }
 }
@@ -0,0 +1,480 @@
+use std::ops::Mul;
+
+use crate::{
+    aabb::AABB,
+    common::{degrees_to_radians, FP},
+    vec3::{Point3, Vec3},
+};
+
+/// Row major 4x4 matrix, transforming column vectors. `a * b` applies `b` first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub m: [[FP; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Self = Self {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn new(m: [[FP; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Self::new([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(scale: Vec3) -> Self {
+        Self::new([
+            [scale.x, 0.0, 0.0, 0.0],
+            [0.0, scale.y, 0.0, 0.0],
+            [0.0, 0.0, scale.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Counterclockwise rotation by `angle` degrees around `axis`, looking down the axis.
+    pub fn rotation(axis: Vec3, angle: FP) -> Self {
+        let a = axis.normalize();
+        let (sin, cos) = degrees_to_radians(angle).sin_cos();
+        let t = 1.0 - cos;
+        Self::new([
+            [
+                t * a.x * a.x + cos,
+                t * a.x * a.y - sin * a.z,
+                t * a.x * a.z + sin * a.y,
+                0.0,
+            ],
+            [
+                t * a.x * a.y + sin * a.z,
+                t * a.y * a.y + cos,
+                t * a.y * a.z - sin * a.x,
+                0.0,
+            ],
+            [
+                t * a.x * a.z - sin * a.y,
+                t * a.y * a.z + sin * a.x,
+                t * a.z * a.z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self::new(m)
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::IDENTITY.m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+            for row in 0..4 {
+                let factor = a[row][col];
+                if row == col || factor == 0.0 {
+                    continue;
+                }
+                for k in 0..4 {
+                    a[row][k] -= factor * a[col][k];
+                    inv[row][k] -= factor * inv[col][k];
+                }
+            }
+        }
+
+        Some(Self::new(inv))
+    }
+
+    /// Determinant of the upper left 3x3 part, negative for mirroring transforms.
+    pub fn determinant3(&self) -> FP {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    fn lerp(&self, other: &Self, t: FP) -> Self {
+        let mut m = self.m;
+        for (row, other_row) in m.iter_mut().zip(&other.m) {
+            for (value, other_value) in row.iter_mut().zip(other_row) {
+                *value += (other_value - *value) * t;
+            }
+        }
+        Self::new(m)
+    }
+
+    /// Whether the bottom row is `[0, 0, 0, 1]`, so no projection is involved.
+    pub fn is_affine(&self) -> bool {
+        self.m[3] == [0.0, 0.0, 0.0, 1.0]
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        self.transform_vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    /// Transforms a direction, ignoring the translation.
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Box around the transformed corners of `bbox`.
+    pub fn transform_aabb(&self, bbox: &AABB) -> AABB {
+        let mut min = Point3::INFINITY;
+        let mut max = Point3::NEG_INFINITY;
+
+        for corner in 0..8 {
+            let pick = |n: usize| {
+                let interval = bbox.axis(n);
+                if corner & (1 << n) == 0 {
+                    interval.min
+                } else {
+                    interval.max
+                }
+            };
+            let p = self.transform_point(&Point3::new(pick(0), pick(1), pick(2)));
+            for c in 0..3 {
+                min[c] = min[c].min(p[c]);
+                max[c] = max[c].max(p[c]);
+            }
+        }
+
+        AABB::new_from_points(min, max)
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Self::new(m)
+    }
+}
+
+/// Unit quaternion, for rotations that can be interpolated.
+#[derive(Debug, Clone, Copy)]
+struct Quaternion {
+    w: FP,
+    v: Vec3,
+}
+
+impl Quaternion {
+    /// From the upper left 3x3 part of `m`, which has to be a rotation.
+    fn from_matrix(m: &Mat4) -> Self {
+        let m = &m.m;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            return Self {
+                w: 0.25 * s,
+                v: Vec3::new(m[2][1] - m[1][2], m[0][2] - m[2][0], m[1][0] - m[0][1]) / s,
+            };
+        }
+
+        // Largest diagonal element first, for precision
+        let i = (0..3).max_by(|&a, &b| m[a][a].total_cmp(&m[b][b])).unwrap();
+        let (j, k) = ((i + 1) % 3, (i + 2) % 3);
+        let s = (1.0 + m[i][i] - m[j][j] - m[k][k]).sqrt() * 2.0;
+        let mut v = Vec3::ZERO;
+        v[i] = 0.25 * s;
+        v[j] = (m[j][i] + m[i][j]) / s;
+        v[k] = (m[k][i] + m[i][k]) / s;
+        Self {
+            w: (m[k][j] - m[j][k]) / s,
+            v,
+        }
+    }
+
+    fn to_matrix(self) -> Mat4 {
+        let Self { w, v } = self;
+        let (x, y, z) = (v.x, v.y, v.z);
+        Mat4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    fn dot(&self, other: &Self) -> FP {
+        self.w * other.w + self.v.dot(&other.v)
+    }
+
+    /// Rotates at a constant speed along the shorter way from `self` to `other`.
+    fn slerp(&self, other: &Self, t: FP) -> Self {
+        let (other, cos) = match self.dot(other) {
+            cos if cos < 0.0 => (
+                Self {
+                    w: -other.w,
+                    v: -other.v,
+                },
+                -cos,
+            ),
+            cos => (*other, cos),
+        };
+        let (a, b) = if cos > 0.9995 {
+            // Nearly parallel, where linear interpolation is as good and stays stable
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        let q = Self {
+            w: a * self.w + b * other.w,
+            v: a * self.v + b * other.v,
+        };
+        let length = q.dot(&q).sqrt();
+        Self {
+            w: q.w / length,
+            v: q.v / length,
+        }
+    }
+}
+
+/// An affine matrix split into translation, rotation and the remaining scale and shear.
+#[derive(Debug, Clone, Copy)]
+struct Decomposition {
+    translation: Vec3,
+    rotation: Quaternion,
+    scale: Mat4,
+}
+
+impl Decomposition {
+    fn new(matrix: &Mat4) -> Self {
+        let translation = Vec3::new(matrix.m[0][3], matrix.m[1][3], matrix.m[2][3]);
+        let mut linear = *matrix;
+        for row in &mut linear.m[..3] {
+            row[3] = 0.0;
+        }
+
+        // Polar decomposition, averaging the matrix with its inverse transpose until only the
+        // rotation is left
+        let mut rotation = linear;
+        for _ in 0..100 {
+            let Some(inverse) = rotation.inverse() else {
+                break;
+            };
+            let next = rotation.lerp(&inverse.transpose(), 0.5);
+            let change = (0..3)
+                .flat_map(|i| (0..3).map(move |j| (i, j)))
+                .map(|(i, j)| (next.m[i][j] - rotation.m[i][j]).abs())
+                .fold(0.0, FP::max);
+            rotation = next;
+            if change < 1e-12 {
+                break;
+            }
+        }
+        // A mirroring matrix leaves a reflection, which goes to the scale instead
+        if rotation.determinant3() < 0.0 {
+            for row in &mut rotation.m[..3] {
+                for value in &mut row[..3] {
+                    *value = -*value;
+                }
+            }
+        }
+
+        Self {
+            translation,
+            rotation: Quaternion::from_matrix(&rotation),
+            scale: rotation.transpose() * linear,
+        }
+    }
+
+    fn matrix(&self) -> Mat4 {
+        Mat4::translation(self.translation) * self.rotation.to_matrix() * self.scale
+    }
+}
+
+/// Motion from one affine matrix to another over the shutter time `[0, 1]`, moving in a
+/// straight line, rotating at a constant speed and scaling linearly. Rotations of more than
+/// half a turn take the shorter way around.
+#[derive(Debug, Clone, Copy)]
+pub struct MatrixMotion {
+    start: Decomposition,
+    end: Decomposition,
+}
+
+impl MatrixMotion {
+    pub fn new(start: &Mat4, end: &Mat4) -> Self {
+        Self {
+            start: Decomposition::new(start),
+            end: Decomposition::new(end),
+        }
+    }
+
+    pub fn at(&self, time: FP) -> Mat4 {
+        let (start, end) = (&self.start, &self.end);
+        Decomposition {
+            translation: start.translation + (end.translation - start.translation) * time,
+            rotation: start.rotation.slerp(&end.rotation, time),
+            scale: start.scale.lerp(&end.scale, time),
+        }
+        .matrix()
+    }
+
+    /// Box around `bbox` over the whole motion. The transformed box is taken at many points
+    /// in time and grown by the most it moves between two of them, to cover the arcs in
+    /// between.
+    pub fn transform_aabb(&self, bbox: &AABB) -> AABB {
+        const STEPS: usize = 32;
+
+        let boxes = (0..=STEPS)
+            .map(|i| self.at(i as FP / STEPS as FP).transform_aabb(bbox))
+            .collect::<Vec<AABB>>();
+        let mut step = 0.0;
+        for pair in boxes.windows(2) {
+            for axis in 0..3 {
+                let (a, b) = (pair[0].axis(axis), pair[1].axis(axis));
+                step = FP::max(step, (a.min - b.min).abs().max((a.max - b.max).abs()));
+            }
+        }
+
+        let bounds = boxes
+            .into_iter()
+            .reduce(AABB::new_from_aabbs)
+            .unwrap_or(AABB::EMPTY);
+        AABB::new(
+            bounds.x.expand(2.0 * step),
+            bounds.y.expand(2.0 * step),
+            bounds.z.expand(2.0 * step),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Mat4, b: &Mat4) {
+        for (row_a, row_b) in a.m.iter().zip(&b.m) {
+            for (x, y) in row_a.iter().zip(row_b) {
+                assert!((x - y).abs() < 1e-9, "{a:?} != {b:?}");
+            }
+        }
+    }
+
+    fn matrices() -> Vec<Mat4> {
+        let shear = Mat4::new([
+            [1.0, 0.5, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.25, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        vec![
+            Mat4::IDENTITY,
+            Mat4::translation(Vec3::new(1.0, -2.0, 3.0)),
+            Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 30.0),
+            Mat4::rotation(Vec3::new(0.2, -1.0, 0.5), 200.0),
+            Mat4::translation(Vec3::new(0.0, 5.0, 0.0))
+                * Mat4::rotation(Vec3::UP, 75.0)
+                * Mat4::scaling(Vec3::new(2.0, 0.5, 3.0)),
+            Mat4::scaling(Vec3::new(-1.0, 1.0, 1.0)) * Mat4::rotation(Vec3::RIGHT, 45.0),
+            Mat4::rotation(Vec3::new(0.0, 0.0, 1.0), -120.0) * shear,
+        ]
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        for matrix in matrices() {
+            let inverse = matrix.inverse().expect("Should've been invertible");
+            assert_close(&(inverse * matrix), &Mat4::IDENTITY);
+            assert_close(&(matrix * inverse), &Mat4::IDENTITY);
+        }
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        assert!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn decomposition_composes_back() {
+        for matrix in matrices() {
+            assert_close(&Decomposition::new(&matrix).matrix(), &matrix);
+        }
+    }
+
+    #[test]
+    fn quaternion_round_trips_rotations() {
+        for angle in [0.0, 90.0, 179.0, 180.0, 270.0] {
+            let rotation = Mat4::rotation(Vec3::new(1.0, 2.0, 3.0), angle);
+            assert_close(&Quaternion::from_matrix(&rotation).to_matrix(), &rotation);
+        }
+    }
+
+    #[test]
+    fn motion_interpolates_between_the_ends() {
+        let start = Mat4::translation(Vec3::new(1.0, 0.0, 0.0));
+        let end = Mat4::translation(Vec3::new(3.0, 0.0, 0.0))
+            * Mat4::rotation(Vec3::UP, 90.0)
+            * Mat4::scaling(Vec3::splat(3.0));
+        let motion = MatrixMotion::new(&start, &end);
+
+        assert_close(&motion.at(0.0), &start);
+        assert_close(&motion.at(1.0), &end);
+        assert_close(
+            &motion.at(0.5),
+            &(Mat4::translation(Vec3::new(2.0, 0.0, 0.0))
+                * Mat4::rotation(Vec3::UP, 45.0)
+                * Mat4::scaling(Vec3::splat(2.0))),
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    cell::RefCell,
+    fmt, fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use image::ImageError;
+
+use crate::{
+    bvh::{BVHBuilder, BVHNode},
+    common::FP,
+    hittable::HittableList,
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    texture::{ImageTexture, SolidColor, Texture},
+    triangle::Triangle,
+    vec3::{Color, Point3, Vec3},
+};
+
+#[derive(Debug)]
+pub enum MeshError {
+    Load(tobj::LoadError),
+    Texture { path: PathBuf, err: ImageError },
+    NoFaces,
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Load(err) => write!(f, "{}", err),
+            MeshError::Texture { path, err } => write!(f, "{}: {}", path.display(), err),
+            MeshError::NoFaces => write!(f, "the mesh has no faces"),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+/// Loads a Wavefront OBJ file and the MTL libraries it references into a [`BVHNode`] of
+/// [`Triangle`]s built with `bvh`. Faces without a material, or whose MTL library can't be
+/// loaded, use `default_material`. The paths of the OBJ, MTL and texture files get appended to
+/// `files`.
+pub fn load_obj(
+    path: &str,
+    default_material: Arc<dyn Material>,
+    bvh: BVHBuilder,
+    files: &mut Vec<PathBuf>,
+) -> Result<BVHNode, MeshError> {
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    files.push(PathBuf::from(path));
+
+    // Same as `tobj::load_obj`, but keeping track of the material libraries
+    let file =
+        fs::File::open(path).map_err(|_| MeshError::Load(tobj::LoadError::OpenFileFailed))?;
+    let libraries = RefCell::new(Vec::new());
+    let (models, materials) = tobj::load_obj_buf(
+        &mut BufReader::new(file),
+        &tobj::GPU_LOAD_OPTIONS,
+        |library| {
+            let library = dir.join(library);
+            libraries.borrow_mut().push(library.clone());
+            tobj::load_mtl(library)
+        },
+    )
+    .map_err(MeshError::Load)?;
+    files.append(&mut libraries.into_inner());
+    // The geometry is still usable, and the libraries are watched in case they show up later
+    let materials = materials.unwrap_or_else(|err| {
+        eprintln!(
+            "{}: no materials ({}), using the default material",
+            path, err
+        );
+        Vec::new()
+    });
+
+    let materials = materials
+        .iter()
+        .map(|material| convert_material(material, dir, files))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut triangles = HittableList::default();
+
+    for model in &models {
+        let mesh = &model.mesh;
+        let mat = mesh
+            .material_id
+            .and_then(|id| materials.get(id))
+            .unwrap_or(&default_material);
+
+        let position = |i: usize| {
+            Point3::new(
+                mesh.positions[3 * i] as FP,
+                mesh.positions[3 * i + 1] as FP,
+                mesh.positions[3 * i + 2] as FP,
+            )
+        };
+        let normal = |i: usize| {
+            Vec3::new(
+                mesh.normals[3 * i] as FP,
+                mesh.normals[3 * i + 1] as FP,
+                mesh.normals[3 * i + 2] as FP,
+            )
+        };
+        let uv = |i: usize| (mesh.texcoords[2 * i] as FP, mesh.texcoords[2 * i + 1] as FP);
+
+        for face in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0] as usize, face[1] as usize, face[2] as usize];
+
+            let mut triangle =
+                Triangle::new(position(a), position(b), position(c), Arc::clone(mat));
+            if !mesh.normals.is_empty() {
+                triangle = triangle.with_normals([normal(a), normal(b), normal(c)]);
+            }
+            if !mesh.texcoords.is_empty() {
+                triangle = triangle.with_uvs([uv(a), uv(b), uv(c)]);
+            }
+
+            triangles.add(Arc::new(triangle));
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err(MeshError::NoFaces);
+    }
+    Ok(BVHNode::new_with_builder(&mut triangles, bvh))
+}
+
+fn to_color(c: [f32; 3]) -> Color {
+    Color::new(c[0] as FP, c[1] as FP, c[2] as FP)
+}
+
+/// Maps an MTL material onto the closest of our materials: emissive (`Ke`) surfaces become
+/// lights, transparent ones dielectrics with `Ni` as their index of refraction, mostly
+/// specular ones metals, and everything else is diffuse with `Kd` or `map_Kd` as albedo.
+fn convert_material(
+    material: &tobj::Material,
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<Arc<dyn Material>, MeshError> {
+    let emission = material
+        .unknown_param
+        .get("Ke")
+        .map(|ke| {
+            ke.split_whitespace()
+                .filter_map(|c| c.parse::<FP>().ok())
+                .collect::<Vec<_>>()
+        })
+        .filter(|ke| ke.len() == 3)
+        .map(|ke| Color::new(ke[0], ke[1], ke[2]))
+        .unwrap_or(Color::ZERO);
+    if !emission.near_zero() {
+        return Ok(Arc::new(DiffuseLight::new(Arc::new(SolidColor::from(
+            emission,
+        )))));
+    }
+
+    let transparent = material.dissolve.is_some_and(|d| d < 1.0)
+        || matches!(material.illumination_model, Some(4 | 6 | 7 | 9));
+    if transparent {
+        return Ok(Arc::new(Dielectric::new(
+            material.optical_density.map_or(1.5, |ni| ni as FP),
+        )));
+    }
+
+    let diffuse = material.diffuse.map_or(Color::splat(0.8), to_color);
+    let specular = material.specular.map_or(Color::ZERO, to_color);
+    let max = |c: Color| c.x.max(c.y).max(c.z);
+    if max(specular) > 0.0
+        && (material.illumination_model == Some(3) || max(specular) > max(diffuse))
+    {
+        let shininess = material.shininess.map_or(0.0, |ns| ns as FP);
+        let fuzz = (1.0 - shininess / 1000.0).clamp(0.0, 1.0);
+        return Ok(Arc::new(Metal::new(specular, fuzz)));
+    }
+
+    let albedo: Arc<dyn Texture> = match &material.diffuse_texture {
+        Some(texture) => {
+            let path = dir.join(texture);
+            files.push(path.clone());
+            Arc::new(
+                ImageTexture::open(&path.to_string_lossy())
+                    .map_err(|err| MeshError::Texture { path, err })?,
+            )
+        }
+        None => Arc::new(SolidColor::from(diffuse)),
+    };
+    Ok(Arc::new(Lambertian::new(albedo)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::Hittable, interval::Interval, ray::Ray, vec3::Vec3};
+
+    #[test]
+    fn missing_material_library_falls_back_to_the_default_material() {
+        let dir = std::env::temp_dir().join(format!("missing-mtl-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("Should've created the directory");
+        let path = dir.join("mesh.obj");
+        fs::write(
+            &path,
+            "mtllib missing.mtl\nv -1 -1 -2\nv 1 -1 -2\nv 0 1 -2\nusemtl red\nf 1 2 3\n",
+        )
+        .expect("Should've written the mesh");
+
+        let default_material: Arc<dyn Material> =
+            Arc::new(Lambertian::new(Arc::new(SolidColor::new(0.1, 0.2, 0.3))));
+        let mut files = Vec::new();
+        let mesh = load_obj(
+            &path.to_string_lossy(),
+            Arc::clone(&default_material),
+            BVHBuilder::Sah,
+            &mut files,
+        );
+        fs::remove_dir_all(&dir).expect("Should've removed the directory");
+
+        let mesh = mesh.expect("Should've loaded the geometry");
+        let ray = Ray::new(Vec3::ZERO, Vec3::new(0.0, 0.0, -1.0));
+        let hit = mesh
+            .hit(&ray, &Interval::new(0.001, FP::INFINITY))
+            .expect("Should've hit the mesh");
+        assert!(std::ptr::addr_eq(hit.mat, default_material.as_ref()));
+        assert_eq!(files, [path, dir.join("missing.mtl")]);
+    }
+}
@@ -0,0 +1,26 @@
+use crate::vec3::Vec3;
+
+/// Orthonormal basis with `w` aligned to a given direction.
+pub struct ONB {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl ONB {
+    pub fn new(n: &Vec3) -> Self {
+        let w = n.normalize();
+        let a = if w.x.abs() > 0.9 {
+            Vec3::UP
+        } else {
+            Vec3::RIGHT
+        };
+        let v = w.cross(&a).normalize();
+        let u = w.cross(&v);
+        Self { u, v, w }
+    }
+
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+}
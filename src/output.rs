@@ -0,0 +1,137 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use image::{
+    codecs::{
+        hdr::HdrEncoder,
+        openexr::OpenExrEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+    },
+    ColorType, ImageEncoder, ImageResult, Rgb,
+};
+
+use crate::{color::ToneMapping, vec3::Color};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// 8-bit gamma corrected PNG
+    #[default]
+    Png,
+    /// 32-bit float linear OpenEXR
+    Exr,
+    /// Radiance RGBE
+    Hdr,
+    /// 32-bit float linear Portable Float Map
+    Pfm,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Exr => "exr",
+            OutputFormat::Hdr => "hdr",
+            OutputFormat::Pfm => "pfm",
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Self> {
+        OutputFormat::value_variants()
+            .iter()
+            .find(|format| format.extension().eq_ignore_ascii_case(extension))
+            .copied()
+    }
+
+    /// Picks the format from `format` if given, otherwise from the extension of `output`,
+    /// falling back to PNG. The extension is appended to `output` when it doesn't match.
+    pub fn resolve(output: &str, format: Option<OutputFormat>) -> (PathBuf, OutputFormat) {
+        let path = PathBuf::from(output);
+        let from_extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(OutputFormat::from_extension);
+
+        match (format, from_extension) {
+            (Some(format), Some(existing)) if format == existing => (path, format),
+            (None, Some(existing)) => (path, existing),
+            (format, _) => {
+                let format = format.unwrap_or_default();
+                (
+                    PathBuf::from(format!("{}.{}", output, format.extension())),
+                    format,
+                )
+            }
+        }
+    }
+}
+
+/// `path` with the frame number before its extension, e.g. `output.0042.png`.
+pub fn frame_path(path: &Path, frame: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}.{:04}", stem, frame);
+    if let Some(extension) = path.extension() {
+        name = format!("{}.{}", name, extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+/// Writes linear `pixels` to `path`. PNG output is tone mapped to 8 bits with `tone_mapping`
+/// while the other formats store the raw radiance values.
+pub fn save_image(
+    path: &Path,
+    format: OutputFormat,
+    tone_mapping: &ToneMapping,
+    pixels: &[Color],
+    width: usize,
+    height: usize,
+) -> ImageResult<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    match format {
+        OutputFormat::Png => {
+            let bytes = pixels
+                .iter()
+                .flat_map(|c| tone_mapping.color_to_rgb(c))
+                .collect::<Vec<u8>>();
+            PngEncoder::new_with_quality(writer, CompressionType::Default, FilterType::Adaptive)
+                .write_image(&bytes, width as u32, height as u32, ColorType::Rgb8)
+        }
+        OutputFormat::Exr => {
+            let bytes = pixels
+                .iter()
+                .flat_map(|c| [c.x as f32, c.y as f32, c.z as f32])
+                .flat_map(f32::to_ne_bytes)
+                .collect::<Vec<u8>>();
+            OpenExrEncoder::new(writer).write_image(
+                &bytes,
+                width as u32,
+                height as u32,
+                ColorType::Rgb32F,
+            )
+        }
+        OutputFormat::Hdr => {
+            let rgb = pixels
+                .iter()
+                .map(|c| Rgb([c.x as f32, c.y as f32, c.z as f32]))
+                .collect::<Vec<_>>();
+            HdrEncoder::new(writer).encode(&rgb, width, height)
+        }
+        OutputFormat::Pfm => {
+            // Scanlines are stored bottom to top, a negative scale means little endian
+            write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+            for row in pixels.chunks_exact(width).rev() {
+                for c in row {
+                    for v in [c.x as f32, c.y as f32, c.z as f32] {
+                        writer.write_all(&v.to_le_bytes())?;
+                    }
+                }
+            }
+            writer.flush()?;
+            Ok(())
+        }
+    }
+}
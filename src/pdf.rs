@@ -0,0 +1,70 @@
+use crate::{
+    common::{FP, PI},
+    hittable::Hittable,
+    onb::ONB,
+    vec3::{Point3, Vec3},
+};
+
+/// A probability density over directions that can also be sampled.
+pub trait PDF {
+    fn value(&self, direction: &Vec3) -> FP;
+    /// Maps the uniform sample `u` to a direction distributed by this density.
+    fn generate(&self, u: (FP, FP)) -> Vec3;
+}
+
+/// Uniform density over the unit sphere.
+pub struct SpherePDF;
+
+impl PDF for SpherePDF {
+    fn value(&self, _direction: &Vec3) -> FP {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self, u: (FP, FP)) -> Vec3 {
+        Vec3::random_on_unit_sphere(u)
+    }
+}
+
+/// Cosine weighted density over the hemisphere around a normal.
+pub struct CosinePDF {
+    uvw: ONB,
+}
+
+impl CosinePDF {
+    pub fn new(w: &Vec3) -> Self {
+        Self { uvw: ONB::new(w) }
+    }
+}
+
+impl PDF for CosinePDF {
+    fn value(&self, direction: &Vec3) -> FP {
+        let cosine_theta = direction.normalize().dot(&self.uvw.w);
+        (cosine_theta / PI).max(0.0)
+    }
+
+    fn generate(&self, u: (FP, FP)) -> Vec3 {
+        self.uvw.local(&Vec3::random_cosine_direction(u))
+    }
+}
+
+/// Density of directions from `origin` towards the objects of a hittable.
+pub struct HittablePDF<'a> {
+    objects: &'a dyn Hittable,
+    origin: Point3,
+}
+
+impl<'a> HittablePDF<'a> {
+    pub fn new(objects: &'a dyn Hittable, origin: Point3) -> Self {
+        Self { objects, origin }
+    }
+}
+
+impl<'a> PDF for HittablePDF<'a> {
+    fn value(&self, direction: &Vec3) -> FP {
+        self.objects.pdf_value(&self.origin, direction)
+    }
+
+    fn generate(&self, u: (FP, FP)) -> Vec3 {
+        self.objects.random(&self.origin, u)
+    }
+}
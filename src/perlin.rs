@@ -1,6 +1,7 @@
-use rand::Rng;
-
-use crate::{common::FP, vec3::Vec3};
+use crate::{
+    common::{self, FP},
+    vec3::Vec3,
+};
 
 const POINT_COUNT: i32 = 256;
 
@@ -73,7 +74,7 @@ impl Perlin {
 
     fn permute(p: &mut Vec<i32>, n: i32) {
         for i in (1..n).rev() {
-            let target = rand::thread_rng().gen_range(0..=i);
+            let target = common::random_range(0..=i);
             p.swap(i as usize, target as usize);
         }
     }
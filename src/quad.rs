@@ -4,7 +4,9 @@ use crate::{
     aabb::AABB,
     common::FP,
     hittable::{HitRecord, Hittable, HittableList},
+    interval::Interval,
     material::Material,
+    ray::Ray,
     vec3::{Point3, Vec3},
 };
 
@@ -17,6 +19,7 @@ pub struct Quad {
     bbox: AABB,
     d: FP,
     normal: Vec3,
+    area: FP,
 }
 
 impl Quad {
@@ -35,6 +38,7 @@ impl Quad {
             bbox: Self::create_bounding_box(&q, &u, &v),
             d,
             normal,
+            area: n.length(),
         }
     }
 
@@ -135,4 +139,27 @@ impl Hittable for Quad {
     fn bounding_box(&self) -> crate::aabb::AABB {
         self.bbox
     }
+
+    fn is_emissive(&self) -> bool {
+        self.mat.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> FP {
+        if let Some(hit) = self.hit(
+            &Ray::new(*origin, *direction),
+            &Interval::new(0.001, FP::INFINITY),
+        ) {
+            let distance_squared = hit.t * hit.t * direction.length_squared();
+            let cosine = (direction.dot(&hit.normal) / direction.length()).abs();
+
+            distance_squared / (cosine * self.area)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, origin: &Point3, (u1, u2): (FP, FP)) -> Vec3 {
+        let p = self.q + (u1 * self.u) + (u2 * self.v);
+        p - *origin
+    }
 }
@@ -0,0 +1,97 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    bvh::{BVHBuilder, BVHNode},
+    camera::Camera,
+    common,
+    hittable::{Hittable, HittableList},
+    scene::{self, SceneError},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A scene rebuilt after one of its files changed.
+pub struct ReloadedScene {
+    pub world: Arc<dyn Hittable>,
+    pub lights: Arc<HittableList>,
+    /// The `[camera]` table as it is now, with the command line overrides applied again
+    pub camera: Camera,
+}
+
+/// Polls the files a scene was built from and rebuilds it on a background thread whenever
+/// one of them changes.
+pub struct SceneWatcher {
+    receiver: mpsc::Receiver<Result<ReloadedScene, SceneError>>,
+}
+
+impl SceneWatcher {
+    /// `files` are the ones the scene at `path` was last built from, with `seed` and `bvh`.
+    /// `overrides` changes every rebuilt camera the way the first one was.
+    pub fn new(
+        path: String,
+        files: Vec<PathBuf>,
+        seed: u64,
+        bvh: BVHBuilder,
+        overrides: impl Fn(&mut Camera) + Send + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut files = files;
+            let mut modified = modification_times(&files);
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                if modification_times(&files) == modified {
+                    continue;
+                }
+                // Give the editor a moment to finish writing
+                thread::sleep(POLL_INTERVAL);
+
+                common::seed_rng(seed, u64::MAX, 0);
+                let result = scene::load(&path, bvh).map(|mut scene| {
+                    files = scene.files;
+                    let lights = Arc::new(scene.world.lights());
+                    let world: Arc<dyn Hittable> = if scene.world.is_empty() {
+                        Arc::new(scene.world)
+                    } else {
+                        Arc::new(BVHNode::new_with_builder(&mut scene.world, bvh))
+                    };
+                    let mut camera = scene.camera;
+                    overrides(&mut camera);
+                    ReloadedScene {
+                        world,
+                        lights,
+                        camera,
+                    }
+                });
+                // A broken scene keeps the files of the last good one, the scene file among them
+                modified = modification_times(&files);
+
+                if sender.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { receiver }
+    }
+
+    /// The latest rebuilt scene, or why it couldn't be built, if anything changed since the
+    /// last call.
+    pub fn try_recv(&self) -> Option<Result<ReloadedScene, SceneError>> {
+        self.receiver.try_iter().last()
+    }
+}
+
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
@@ -1,20 +1,170 @@
 use crate::{
-    camera::Camera, color::color_to_rgb, common::FP, hittable::Hittable, interval::Interval,
-    ray::Ray, vec3::Color,
+    aov::{save_aovs, AOVSample, AOV},
+    camera::Camera,
+    checkpoint::{self, CheckpointError, CheckpointSettings},
+    color::{luminance, ToneMapping},
+    common::{self, FP},
+    controls::{self, CameraControls, LiveCommand, LiveView},
+    filter::{Filter, SplatBuffer},
+    hittable::{Hittable, HittableList},
+    integrator::IntegratorType,
+    output::{save_image, OutputFormat},
+    reload::SceneWatcher,
+    sampler::{Sampler, SamplerType},
+    vec3::Color,
 };
-use fltk::{app, prelude::*, window::Window};
-use image::{codecs::png::CompressionType, ImageEncoder};
+use fltk::{app, enums::Event, prelude::*, window::Window};
 use indicatif::{ProgressBar, ProgressStyle};
 use pixels::{Pixels, SurfaceTexture};
 use rayon::prelude::*;
-use std::{fs::File, sync::Arc, time::Instant};
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Options shared by [`render`] and [`live_render`].
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub seed: u64,
+    pub sampler: SamplerType,
+    pub integrator: IntegratorType,
+    /// Only used by [`render`], the live view keeps refining every pixel
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Samples [`render`] adds to every pixel before moving on to the next pass
+    pub pass_samples: i32,
+    /// [`render`] stops and writes what it has once this much time has passed
+    pub time_limit: Option<Duration>,
+    /// Reconstruction filter [`render`] splats the samples with
+    pub filter: Filter,
+}
+
+/// Where [`render`] writes the image, its AOVs and its checkpoints.
+#[derive(Debug, Clone)]
+pub struct RenderOutput {
+    pub path: PathBuf,
+    pub format: OutputFormat,
+    pub aovs: Vec<AOV>,
+    pub checkpoint: Option<CheckpointSettings>,
+}
+
+/// Stops sampling a pixel once the standard error of its mean luminance falls below
+/// `threshold` times the mean, after at least `min_samples` samples.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveSampling {
+    pub threshold: FP,
+    pub min_samples: i32,
+}
+
+impl AdaptiveSampling {
+    fn converged(&self, stats: &RunningStats) -> bool {
+        stats.count >= self.min_samples.max(2) as u32
+            && stats.standard_error() <= self.threshold * stats.mean
+    }
+}
+
+/// Welford's running mean and variance.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunningStats {
+    pub count: u32,
+    pub mean: FP,
+    pub m2: FP,
+}
 
-pub fn render(camera: Arc<Camera>, world: Arc<dyn Hittable>, output_file_name: String) {
+impl RunningStats {
+    fn push(&mut self, x: FP) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as FP;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    fn standard_error(&self) -> FP {
+        let variance = self.m2 / (self.count - 1) as FP;
+        (variance / self.count as FP).sqrt()
+    }
+}
+
+/// Sums of everything sampled for a pixel so far, `stats.count` being the number of samples.
+/// `color` and `weight` also hold the filtered samples splatted from neighbouring pixels.
+#[derive(Default, Clone, Copy)]
+pub struct PixelState {
+    pub color: Color,
+    pub weight: FP,
+    pub aov: AOVSample,
+    pub stats: RunningStats,
+}
+
+const TILE_SIZE: usize = 32;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Makes a running [`render`] stop sampling and write out the samples it has so far.
+pub fn stop_render() {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+/// Whether [`stop_render`] was called.
+pub fn render_stopped() -> bool {
+    STOP.load(Ordering::Relaxed)
+}
+
+pub fn render(
+    camera: Arc<Camera>,
+    world: Arc<dyn Hittable>,
+    lights: Arc<HittableList>,
+    settings: RenderSettings,
+    output: &RenderOutput,
+) -> Result<(), CheckpointError> {
     let height = camera.image_height;
     let width = camera.image_width;
-    let spp = camera.samples_per_pixel;
+    let spp = camera.samples_per_pixel as u32;
+    let RenderSettings {
+        seed,
+        sampler,
+        integrator,
+        adaptive,
+        pass_samples,
+        time_limit,
+        filter,
+    } = settings;
+    let with_aovs = !output.aovs.is_empty();
+    let integrator = integrator.build(&camera, world.as_ref());
+
+    let mut pixels = match &output.checkpoint {
+        Some(checkpoint) if checkpoint.resume => {
+            let pixels = checkpoint::load(&checkpoint.path, checkpoint.fingerprint, width, height)?;
+            let done = pixels.iter().map(|p| p.stats.count).min().unwrap_or(0);
+            println!(
+                "Resuming {} with {} spp done",
+                checkpoint.path.display(),
+                done
+            );
+            pixels
+        }
+        _ => vec![PixelState::default(); width * height],
+    };
+
+    let tiles = (0..height)
+        .step_by(TILE_SIZE)
+        .flat_map(|y| (0..width).step_by(TILE_SIZE).map(move |x| (x, y)))
+        .collect::<Vec<(usize, usize)>>();
+    let passes = spp.div_ceil(pass_samples.max(1) as u32);
 
-    let bar = &Box::new(ProgressBar::new((width * height / 64) as u64));
+    let now = Instant::now();
+    let deadline = time_limit.map(|time_limit| now + time_limit);
+    let stopped = || STOP.load(Ordering::Relaxed) || deadline.is_some_and(|d| Instant::now() >= d);
+
+    // With a time limit the bar shows the time spent instead of the passes done
+    let bar = &Box::new(ProgressBar::new(match time_limit {
+        Some(time_limit) => time_limit.as_millis() as u64,
+        None => tiles.len() as u64 * passes as u64,
+    }));
     bar.set_prefix("🎥 Rendering");
     bar.set_style(
         ProgressStyle::with_template("{prefix:.bold} [{eta_precise}]▕{bar:64.}▏{percent}%")
@@ -22,62 +172,199 @@ pub fn render(camera: Arc<Camera>, world: Arc<dyn Hittable>, output_file_name: S
             .progress_chars("█▉▊▋▌▍▎▏  "),
     );
 
-    let now = Instant::now();
-    let raw_pixels: Vec<Color> = (0..width * height)
-        .into_par_iter()
-        .map_init(
-            || Arc::clone(&world),
-            |world, screen_pos| {
-                let mut avg_color = Color::ZERO;
-                let i = screen_pos % width;
-                let j = screen_pos / width;
-
-                for _ in 1..=spp {
-                    let r = camera.get_ray(i, j);
-                    let new_color =
-                        ray_color(&r, camera.max_depth, &camera.background, world.as_ref());
-                    avg_color += new_color;
+    // Takes the pixel from the samples it already has up to `target` samples
+    let render_pixel = |world: &dyn Hittable,
+                        sampler: &mut dyn Sampler,
+                        pixel: &mut PixelState,
+                        splats: &mut SplatBuffer,
+                        (i, j): (usize, usize),
+                        target: u32| {
+        while pixel.stats.count < target && !stopped() {
+            if adaptive.is_some_and(|adaptive| adaptive.converged(&pixel.stats)) {
+                break;
+            }
+
+            let sample = pixel.stats.count;
+            common::seed_rng(seed, (j * width + i) as u64, sample as u64);
+            sampler.start_sample(i, j, sample);
+            let (u1, u2) = sampler.get_2d();
+            let film_position = (i as FP + u1, j as FP + u2);
+            let r = camera.get_ray(film_position, sampler);
+            let new_color = integrator.ray_color(&r, world, &lights, sampler);
+            splats.splat(&filter, film_position, new_color);
+            // After the beauty sample, media drawing from the generator would shift it
+            if with_aovs {
+                pixel.aov += AOVSample::new(&r, world);
+            }
+            pixel.stats.push(luminance(&new_color));
+        }
+    };
+
+    let write_image = |pixels: &[PixelState]| {
+        let colors = pixels
+            .iter()
+            .map(|p| {
+                if p.weight > 0.0 {
+                    p.color / p.weight
+                } else {
+                    Color::ZERO
                 }
+            })
+            .collect::<Vec<Color>>();
+        save_image(
+            &output.path,
+            output.format,
+            &camera.tone_mapping,
+            &colors,
+            width,
+            height,
+        )
+    };
 
-                if screen_pos % 64 == 0 {
-                    bar.inc(1);
+    let mut last_checkpoint = Instant::now();
+    for pass in 1..=passes {
+        let target = (pass * pass_samples.max(1) as u32).min(spp);
+
+        // Samples land in a buffer per tile, so tiles can splat across their borders
+        let rendered_tiles: Vec<(Vec<PixelState>, SplatBuffer)> = tiles
+            .par_iter()
+            .map_init(
+                || (Arc::clone(&world), sampler.build(seed, spp)),
+                |(world, sampler), &(x, y)| {
+                    let (x1, y1) = ((x + TILE_SIZE).min(width), (y + TILE_SIZE).min(height));
+                    let mut tile = Vec::with_capacity(TILE_SIZE * TILE_SIZE);
+                    let mut splats = SplatBuffer::new(&filter, (x, y), (x1, y1));
+                    for j in y..y1 {
+                        for i in x..x1 {
+                            let mut pixel = pixels[j * width + i];
+                            render_pixel(
+                                world.as_ref(),
+                                sampler.as_mut(),
+                                &mut pixel,
+                                &mut splats,
+                                (i, j),
+                                target,
+                            );
+                            tile.push(pixel);
+                        }
+                    }
+                    match time_limit {
+                        Some(_) => bar.set_position(now.elapsed().as_millis() as u64),
+                        None => bar.inc(1),
+                    }
+                    (tile, splats)
+                },
+            )
+            .collect();
+
+        for (&(x, y), (tile, splats)) in tiles.iter().zip(rendered_tiles) {
+            let tile_width = (x + TILE_SIZE).min(width) - x;
+            for (k, pixel) in tile.into_iter().enumerate() {
+                let state = &mut pixels[(y + k / tile_width) * width + x + k % tile_width];
+                state.aov = pixel.aov;
+                state.stats = pixel.stats;
+            }
+            for (index, color, weight) in splats.pixels(width, height) {
+                pixels[index].color += color;
+                pixels[index].weight += weight;
+            }
+        }
+
+        let last_pass = pass == passes || stopped();
+        if let Some(checkpoint) = &output.checkpoint {
+            if last_pass || last_checkpoint.elapsed() >= checkpoint.interval {
+                if let Err(err) = checkpoint::save(
+                    &checkpoint.path,
+                    seed,
+                    checkpoint.fingerprint,
+                    width,
+                    height,
+                    &pixels,
+                ) {
+                    bar.println(format!("Checkpoint {}: {}", checkpoint.path.display(), err));
                 }
+                last_checkpoint = Instant::now();
+            }
+        }
 
-                avg_color
-            },
-        )
-        .collect();
+        if last_pass {
+            break;
+        }
+        // A usable image at every point the render could get stopped
+        if let Err(err) = write_image(&pixels) {
+            bar.println(format!("{}: {}", output.path.display(), err));
+        }
+    }
     bar.finish();
     println!("Render time: {:.2?}", now.elapsed());
+    if stopped() {
+        let counts = pixels.iter().map(|p| p.stats.count);
+        println!(
+            "Stopped early with {} to {} spp",
+            counts.clone().min().unwrap_or(0),
+            counts.max().unwrap_or(0)
+        );
+    }
+
+    let sample_counts = pixels.iter().map(|p| p.stats.count).collect::<Vec<u32>>();
+    if adaptive.is_some() {
+        let total = sample_counts.iter().map(|&n| n as u64).sum::<u64>();
+        let average = total as FP / sample_counts.len() as FP;
+        println!(
+            "Samples: {:.1} per pixel, {:.1}% of the maximum",
+            average,
+            100.0 * average / spp as FP
+        );
+    }
 
     {
         let now = Instant::now();
-        let pixels = raw_pixels
-            .into_iter()
-            .flat_map(|c| color_to_rgb(&(c / spp as FP)).to_vec())
-            .collect::<Vec<u8>>();
-        let output = File::create(format!("{}.png", output_file_name)).unwrap();
-        let encoder = image::codecs::png::PngEncoder::new_with_quality(
-            output,
-            CompressionType::Default,
-            image::codecs::png::FilterType::Adaptive,
+        write_image(&pixels).expect("Should've encoded the image into a file.");
+        println!(
+            "{} encoding: {:.2?}",
+            output.format.extension().to_uppercase(),
+            now.elapsed()
         );
-        encoder
-            .write_image(
-                pixels.as_slice(),
-                width as u32,
-                height as u32,
-                image::ColorType::Rgb8,
-            )
-            .expect("Should've encoded the image into a file.");
-        println!("PNG encoding: {:.2?}", now.elapsed());
     }
+
+    if with_aovs {
+        let now = Instant::now();
+        save_aovs(
+            &output.aovs,
+            &pixels.iter().map(|p| p.aov).collect::<Vec<AOVSample>>(),
+            &sample_counts,
+            &output.path,
+            output.format,
+            width,
+            height,
+        )
+        .expect("Should've encoded the AOVs into files.");
+        println!("AOV encoding: {:.2?}", now.elapsed());
+    }
+
+    Ok(())
 }
 
-pub fn live_render(camera: Arc<Camera>, world: Arc<dyn Hittable>) {
+/// Shows the render in a window, restarting it whenever the camera moves or `watcher`
+/// reports a rebuilt scene. Saved images go next to `output_path`.
+pub fn live_render(
+    mut camera: Camera,
+    mut world: Arc<dyn Hittable>,
+    mut lights: Arc<HittableList>,
+    settings: RenderSettings,
+    watcher: Option<SceneWatcher>,
+    output_path: PathBuf,
+) {
     let height = camera.image_height;
     let width = camera.image_width;
-    let spp = camera.samples_per_pixel;
+    let mut spp = camera.samples_per_pixel;
+    let RenderSettings {
+        seed,
+        sampler,
+        integrator: integrator_type,
+        ..
+    } = settings;
+    let mut integrator = integrator_type.build(&camera, world.as_ref());
 
     let app = app::App::default();
     let mut win = Window::default()
@@ -87,6 +374,28 @@ pub fn live_render(camera: Arc<Camera>, world: Arc<dyn Hittable>) {
     win.end();
     win.show();
 
+    // The window owns the controls and sends every changed view to the render loop
+    let (view_sender, view_receiver) = mpsc::channel();
+    let (command_sender, command_receiver) = mpsc::channel();
+    // Shared with the render loop, which moves the view when the scene file does
+    let controls = Rc::new(RefCell::new(CameraControls::new(camera.view(), height)));
+    let window_controls = Rc::clone(&controls);
+    win.handle(move |_, event| {
+        if event == Event::KeyDown {
+            if let Some(command) = LiveCommand::from_key(app::event_key()) {
+                command_sender.send(command).ok();
+                return true;
+            }
+        }
+        let mut controls = window_controls.borrow_mut();
+        let handled = controls.handle(event);
+        if let Some(view) = controls.take_change() {
+            view_sender.send(view).ok();
+        }
+        handled
+    });
+    println!("{}", controls::HELP);
+
     let mut pixels = {
         let pixel_width = win.pixel_w() as u32;
         let pixel_height = win.pixel_h() as u32;
@@ -95,33 +404,151 @@ pub fn live_render(camera: Arc<Camera>, world: Arc<dyn Hittable>) {
     };
 
     let mut raw_pixels: Vec<Color> = vec![Color::ZERO; width * height];
+    // Sums of the first hits, for the normal and albedo views
+    let mut aov_pixels: Vec<AOVSample> = vec![AOVSample::default(); width * height];
     let mut num_samples = 1;
+    let mut reload_error = None;
+    // View of the scene file, kept while it doesn't change so moving around survives reloads
+    let mut scene_view = camera.view();
+    let mut paused = false;
+    let mut live_view = LiveView::default();
 
     while app.wait() {
-        win.set_label(format!("rust-tracing [{}x{}, spp:{}]", width, height, num_samples).as_str());
+        let mut restart = false;
+        if let Some(view) = view_receiver.try_iter().last() {
+            camera.set_view(view);
+            restart = true;
+        }
+        // A broken scene stays on screen as it last was, with the error in the title
+        match watcher.as_ref().and_then(SceneWatcher::try_recv) {
+            Some(Ok(reloaded)) => {
+                println!("Reloaded the scene");
+                world = reloaded.world;
+                lights = reloaded.lights;
+                let mut reloaded_camera = reloaded.camera;
+                if (reloaded_camera.image_width, reloaded_camera.image_height) != (width, height) {
+                    eprintln!("The image size only changes after restarting the live view");
+                    camera.environment = reloaded_camera.environment;
+                } else {
+                    let view = reloaded_camera.view();
+                    if view == scene_view {
+                        reloaded_camera.set_view(camera.view());
+                    } else {
+                        scene_view = view;
+                        controls.borrow_mut().set_view(view);
+                    }
+                    spp = reloaded_camera.samples_per_pixel;
+                    camera = reloaded_camera;
+                }
+                reload_error = None;
+                restart = true;
+            }
+            Some(Err(err)) => {
+                eprintln!("{}", err);
+                let message = err.to_string();
+                reload_error = message.lines().next().map(str::to_owned);
+            }
+            None => {}
+        }
+        if restart {
+            integrator = integrator_type.build(&camera, world.as_ref());
+            raw_pixels.fill(Color::ZERO);
+            aov_pixels.fill(AOVSample::default());
+            num_samples = 1;
+            paused = false;
+        }
+
+        let mut redraw = false;
+        for command in command_receiver.try_iter() {
+            match command {
+                LiveCommand::Save => {
+                    let done = num_samples - 1;
+                    let counts = vec![done.max(1) as u32; width * height];
+                    for format in [OutputFormat::Png, OutputFormat::Exr] {
+                        let path = output_path.with_extension(format.extension());
+                        let result = match live_view.aov() {
+                            None => save_image(
+                                &path,
+                                format,
+                                &camera.tone_mapping,
+                                &raw_pixels,
+                                width,
+                                height,
+                            ),
+                            Some(aov) => save_aovs(
+                                &[aov],
+                                &aov_pixels,
+                                &counts,
+                                &path,
+                                format,
+                                width,
+                                height,
+                            ),
+                        };
+                        match result {
+                            Ok(()) => println!("Saved {} with {} spp", path.display(), done),
+                            Err(err) => eprintln!("{}: {}", path.display(), err),
+                        }
+                    }
+                }
+                LiveCommand::TogglePause => paused = !paused,
+                LiveCommand::Show(view) => {
+                    live_view = view;
+                    redraw = true;
+                }
+            }
+        }
+
+        let mut label = format!("rust-tracing [{}x{}, spp:{}]", width, height, num_samples);
+        if paused {
+            label = format!("{} paused", label);
+        }
+        if let Some(err) = &reload_error {
+            label = format!("{} {}", label, err);
+        }
+        win.set_label(&label);
 
         // Draw the current frame
-        if num_samples < spp {
-            raw_pixels.par_iter_mut().enumerate().for_each_init(
-                || Arc::clone(&world),
-                |world, (screen_pos, avg_color)| {
-                    let i = screen_pos % width;
-                    let j = screen_pos / width;
-
-                    let r = camera.get_ray(i, j);
-                    let new_color =
-                        ray_color(&r, camera.max_depth, &camera.background, world.as_ref());
-                    *avg_color += (new_color - *avg_color) / num_samples as FP;
-                },
-            );
+        if num_samples < spp && !paused {
+            raw_pixels
+                .par_iter_mut()
+                .zip(aov_pixels.par_iter_mut())
+                .enumerate()
+                .for_each_init(
+                    || (Arc::clone(&world), sampler.build(seed, spp as u32)),
+                    |(world, sampler), (screen_pos, (avg_color, aov))| {
+                        let i = screen_pos % width;
+                        let j = screen_pos / width;
+
+                        common::seed_rng(seed, screen_pos as u64, num_samples as u64);
+                        sampler.start_sample(i, j, num_samples as u32 - 1);
+                        let (u1, u2) = sampler.get_2d();
+                        let r = camera.get_ray((i as FP + u1, j as FP + u2), sampler.as_mut());
+                        let new_color =
+                            integrator.ray_color(&r, world.as_ref(), &lights, sampler.as_mut());
+                        *avg_color += (new_color - *avg_color) / num_samples as FP;
+                        *aov += AOVSample::new(&r, world.as_ref());
+                    },
+                );
             num_samples += 1;
+            redraw = true;
+        }
 
+        if redraw {
+            let count = (num_samples - 1).max(1) as FP;
             let frame = pixels.frame_mut();
             frame
                 .par_chunks_exact_mut(4)
                 .enumerate()
                 .for_each(|(i, pixel)| {
-                    let [r, g, b] = color_to_rgb(&raw_pixels[i]);
+                    let [r, g, b] = match live_view {
+                        LiveView::Beauty => camera.tone_mapping.color_to_rgb(&raw_pixels[i]),
+                        LiveView::Normal => ToneMapping::default()
+                            .color_to_rgb(&(0.5 * (aov_pixels[i].normal / count + Color::ONE))),
+                        LiveView::Albedo => {
+                            ToneMapping::default().color_to_rgb(&(aov_pixels[i].albedo / count))
+                        }
+                    };
                     let rgba = [r, g, b, 0xff];
                     pixel.copy_from_slice(&rgba);
                 });
@@ -136,20 +563,96 @@ pub fn live_render(camera: Arc<Camera>, world: Arc<dyn Hittable>) {
     }
 }
 
-fn ray_color(ray: &Ray, depth: i32, background: &Color, world: &dyn Hittable) -> Color {
-    if depth <= 0 {
-        return Color::ZERO;
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::CameraSettings, filter::FilterType, material::Lambertian, sphere::Sphere,
+        texture::SolidColor, vec3::Point3,
+    };
+    use std::fs;
 
-    if let Some(hit) = world.hit(ray, &Interval::new(0.001, FP::INFINITY)) {
-        let color_from_emission = hit.mat.emitted(hit.u, hit.v, &hit.p);
+    fn scene(samples_per_pixel: i32) -> (Arc<Camera>, Arc<dyn Hittable>) {
+        let camera = Camera::new(CameraSettings {
+            aspect_ratio: 1.0,
+            image_width: 8,
+            samples_per_pixel,
+            background: Color::splat(0.5),
+            ..Default::default()
+        });
+        let mut world = HittableList::default();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, -2.0),
+            1.0,
+            Arc::new(Lambertian::new(Arc::new(SolidColor::new(0.5, 0.5, 0.5)))),
+        )));
+        (Arc::new(camera), Arc::new(world))
+    }
 
-        if let Some((scattered, attenuation)) = hit.mat.scatter(ray, &hit) {
-            color_from_emission + attenuation * ray_color(&scattered, depth - 1, background, world)
-        } else {
-            color_from_emission
+    fn settings(time_limit: Option<Duration>) -> RenderSettings {
+        RenderSettings {
+            seed: 1,
+            sampler: SamplerType::default(),
+            integrator: IntegratorType::default(),
+            adaptive: None,
+            pass_samples: 1,
+            time_limit,
+            filter: Filter::new(FilterType::default(), None),
         }
-    } else {
-        *background
+    }
+
+    /// Renders into the temporary directory and returns the sample counts of the pixels.
+    fn render_counts(name: &str, samples_per_pixel: i32, time_limit: Option<Duration>) -> Vec<u32> {
+        let dir = std::env::temp_dir();
+        let name = format!("{}-{}", name, std::process::id());
+        let output = RenderOutput {
+            path: dir.join(format!("{name}.pfm")),
+            format: OutputFormat::Pfm,
+            aovs: Vec::new(),
+            checkpoint: Some(CheckpointSettings {
+                path: dir.join(format!("{name}.checkpoint")),
+                interval: Duration::ZERO,
+                fingerprint: 0,
+                resume: false,
+            }),
+        };
+        let (camera, world) = scene(samples_per_pixel);
+        let (width, height) = (camera.image_width, camera.image_height);
+        render(
+            camera,
+            world,
+            Arc::new(HittableList::default()),
+            settings(time_limit),
+            &output,
+        )
+        .expect("Should've rendered");
+
+        let checkpoint_path = &output.checkpoint.as_ref().unwrap().path;
+        let pixels = checkpoint::load(checkpoint_path, 0, width, height);
+        let image = fs::metadata(&output.path);
+        fs::remove_file(checkpoint_path).expect("Should've removed the checkpoint");
+        fs::remove_file(&output.path).expect("Should've removed the image");
+
+        assert!(image.is_ok_and(|image| image.len() > 0));
+        pixels
+            .expect("Should've loaded the checkpoint")
+            .iter()
+            .map(|pixel| pixel.stats.count)
+            .collect()
+    }
+
+    #[test]
+    fn passes_add_up_to_the_samples_per_pixel() {
+        let counts = render_counts("passes-add-up", 3, None);
+
+        assert!(counts.iter().all(|&count| count == 3));
+    }
+
+    #[test]
+    fn zero_time_limit_stops_after_the_first_pass() {
+        // Without the time limit this would take billions of passes
+        let counts = render_counts("zero-time-limit", i32::MAX, Some(Duration::ZERO));
+
+        assert!(counts.iter().all(|&count| count == 0));
     }
 }
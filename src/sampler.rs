@@ -0,0 +1,409 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+
+use crate::common::{self, splitmix64, FP};
+
+/// Supplies the numbers a camera path consumes, one dimension after another: pixel
+/// position, lens, time, then light and BSDF directions for every bounce.
+pub trait Sampler {
+    /// Restarts the dimensions for sample `index` of pixel (`i`, `j`).
+    fn start_sample(&mut self, i: usize, j: usize, index: u32);
+    fn get_1d(&mut self) -> FP;
+    fn get_2d(&mut self) -> (FP, FP);
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SamplerType {
+    /// Uniform random numbers
+    #[default]
+    Independent,
+    /// Jittered strata, shuffled per pixel and dimension
+    Stratified,
+    /// Halton sequence rotated per pixel
+    Halton,
+    /// Owen scrambled Sobol sequence
+    Sobol,
+    /// Sobol sequence rotated by a blue noise mask, leaving only high frequency noise
+    BlueNoise,
+}
+
+impl SamplerType {
+    pub fn build(&self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        let dimensions = Dimensions {
+            seed,
+            ..Default::default()
+        };
+        match self {
+            SamplerType::Independent => Box::new(IndependentSampler),
+            SamplerType::Stratified => Box::new(StratifiedSampler {
+                samples_per_pixel: samples_per_pixel.max(1),
+                dimensions,
+            }),
+            SamplerType::Halton => Box::new(HaltonSampler { dimensions }),
+            SamplerType::Sobol => Box::new(SobolSampler { dimensions }),
+            SamplerType::BlueNoise => Box::new(BlueNoiseSampler {
+                mask: blue_noise_mask(),
+                dimensions,
+            }),
+        }
+    }
+}
+
+/// Tracks the current pixel, sample and dimension of the deterministic samplers.
+#[derive(Default)]
+struct Dimensions {
+    seed: u64,
+    pixel: (usize, usize),
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl Dimensions {
+    fn start(&mut self, i: usize, j: usize, index: u32) {
+        self.pixel = (i, j);
+        self.pixel_seed = splitmix64(self.seed ^ splitmix64(((j as u64) << 32) | i as u64));
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    /// Moves to the next dimension, returning a hash of it and the current pixel.
+    fn next_pixel_seed(&mut self) -> u64 {
+        self.dimension += 1;
+        splitmix64(self.pixel_seed ^ splitmix64(self.dimension))
+    }
+
+    /// Moves to the next dimension, returning a hash of it shared by all pixels.
+    fn next_global_seed(&mut self) -> u64 {
+        self.dimension += 1;
+        splitmix64(self.seed ^ splitmix64(self.dimension))
+    }
+}
+
+struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, _i: usize, _j: usize, _index: u32) {}
+
+    fn get_1d(&mut self) -> FP {
+        common::random()
+    }
+
+    fn get_2d(&mut self) -> (FP, FP) {
+        (common::random(), common::random())
+    }
+}
+
+struct StratifiedSampler {
+    samples_per_pixel: u32,
+    dimensions: Dimensions,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, i: usize, j: usize, index: u32) {
+        self.dimensions.start(i, j, index);
+    }
+
+    fn get_1d(&mut self) -> FP {
+        let strata = self.samples_per_pixel;
+        let seed = self.dimensions.next_pixel_seed() as u32;
+        let stratum = permute(self.dimensions.index % strata, strata, seed);
+        (stratum as FP + common::random::<FP>()) / strata as FP
+    }
+
+    fn get_2d(&mut self) -> (FP, FP) {
+        let n = (self.samples_per_pixel as FP).sqrt().ceil() as u32;
+        let seed = self.dimensions.next_pixel_seed() as u32;
+        let stratum = permute(self.dimensions.index % (n * n), n * n, seed);
+        (
+            ((stratum % n) as FP + common::random::<FP>()) / n as FP,
+            ((stratum / n) as FP + common::random::<FP>()) / n as FP,
+        )
+    }
+}
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+struct HaltonSampler {
+    dimensions: Dimensions,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, i: usize, j: usize, index: u32) {
+        self.dimensions.start(i, j, index);
+    }
+
+    fn get_1d(&mut self) -> FP {
+        let rotation = to_unit(self.dimensions.next_pixel_seed() as u32);
+        // Bases this large have poor projections anyway, deep bounces go back to random
+        let Some(&base) = PRIMES.get(self.dimensions.dimension as usize - 1) else {
+            return common::random();
+        };
+        (radical_inverse(base, self.dimensions.index as u64) + rotation).fract()
+    }
+
+    fn get_2d(&mut self) -> (FP, FP) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+struct SobolSampler {
+    dimensions: Dimensions,
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, i: usize, j: usize, index: u32) {
+        self.dimensions.start(i, j, index);
+    }
+
+    fn get_1d(&mut self) -> FP {
+        self.get_2d().0
+    }
+
+    fn get_2d(&mut self) -> (FP, FP) {
+        let seed = self.dimensions.next_pixel_seed();
+        sobol_2d(self.dimensions.index, seed)
+    }
+}
+
+struct BlueNoiseSampler {
+    mask: &'static [FP],
+    dimensions: Dimensions,
+}
+
+impl BlueNoiseSampler {
+    fn mask_value(&self, offset: u64) -> FP {
+        let (i, j) = self.dimensions.pixel;
+        let x = (i + (offset as usize % MASK_SIZE)) % MASK_SIZE;
+        let y = (j + ((offset >> 8) as usize % MASK_SIZE)) % MASK_SIZE;
+        self.mask[y * MASK_SIZE + x]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, i: usize, j: usize, index: u32) {
+        self.dimensions.start(i, j, index);
+    }
+
+    fn get_1d(&mut self) -> FP {
+        self.get_2d().0
+    }
+
+    fn get_2d(&mut self) -> (FP, FP) {
+        // Every pixel walks the same sequence, only the mask decides where it starts
+        let seed = self.dimensions.next_global_seed();
+        let (x, y) = sobol_2d(self.dimensions.index, seed);
+        (
+            (x + self.mask_value(seed)).fract(),
+            (y + self.mask_value(seed >> 16)).fract(),
+        )
+    }
+}
+
+#[inline]
+fn to_unit(x: u32) -> FP {
+    x as FP / 4_294_967_296.0
+}
+
+fn radical_inverse(base: u64, mut a: u64) -> FP {
+    let inv_base = 1.0 / base as FP;
+    let mut reversed = 0;
+    let mut inv_base_n = 1.0;
+    while a > 0 {
+        let next = a / base;
+        reversed = reversed * base + (a - next * base);
+        inv_base_n *= inv_base;
+        a = next;
+    }
+    (reversed as FP * inv_base_n).min(1.0 - FP::EPSILON)
+}
+
+/// The first two Sobol dimensions at `index`, shuffled and Owen scrambled with `seed` as
+/// described in Burley's "Practical Hash-based Owen Scrambling".
+fn sobol_2d(index: u32, seed: u64) -> (FP, FP) {
+    let index = nested_uniform_scramble(index, seed as u32);
+
+    let mut y = 0;
+    let mut v = 1 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+
+    (
+        to_unit(nested_uniform_scramble(
+            index.reverse_bits(),
+            (seed >> 32) as u32,
+        )),
+        to_unit(nested_uniform_scramble(y, splitmix64(seed) as u32)),
+    )
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// Kensler's hashed permutation of `0..len`, mapping `i` to its position under `seed`.
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    i.wrapping_add(seed) % len
+}
+
+const MASK_SIZE: usize = 64;
+
+fn blue_noise_mask() -> &'static [FP] {
+    static MASK: OnceLock<Vec<FP>> = OnceLock::new();
+    MASK.get_or_init(void_and_cluster)
+}
+
+/// Ulichney's void-and-cluster method. Texels are ranked by the order they get added to an
+/// evenly spread point set, so every threshold of the mask is evenly spread as well.
+fn void_and_cluster() -> Vec<FP> {
+    const LEN: usize = MASK_SIZE * MASK_SIZE;
+    const SIGMA: FP = 1.5;
+
+    let kernel = (0..LEN)
+        .map(|k| {
+            let (dx, dy) = (k % MASK_SIZE, k / MASK_SIZE);
+            let dx = dx.min(MASK_SIZE - dx) as FP;
+            let dy = dy.min(MASK_SIZE - dy) as FP;
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect::<Vec<FP>>();
+
+    let mut points = PointSet {
+        kernel,
+        points: vec![false; LEN],
+        energy: vec![0.0; LEN],
+    };
+
+    // Fixed seed, the mask is the same for every render
+    let mut rng = Pcg32::seed_from_u64(0);
+    let initial = LEN / 10;
+    while points.points.iter().filter(|&&p| p).count() < initial {
+        let p = rng.gen_range(0..LEN);
+        if !points.points[p] {
+            points.toggle(p);
+        }
+    }
+
+    // Move points from the tightest cluster into the largest void until it settles
+    loop {
+        let cluster = points.tightest_cluster();
+        points.toggle(cluster);
+        let void = points.largest_void();
+        points.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; LEN];
+    let prototype = (points.points.clone(), points.energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = points.tightest_cluster();
+        points.toggle(cluster);
+        ranks[cluster] = rank;
+    }
+    (points.points, points.energy) = prototype;
+    for rank in initial..LEN {
+        let void = points.largest_void();
+        points.toggle(void);
+        ranks[void] = rank;
+    }
+
+    ranks
+        .into_iter()
+        .map(|rank| (rank as FP + 0.5) / LEN as FP)
+        .collect()
+}
+
+/// Binary pattern on a torus with the Gaussian filtered density of its points.
+struct PointSet {
+    kernel: Vec<FP>,
+    points: Vec<bool>,
+    energy: Vec<FP>,
+}
+
+impl PointSet {
+    fn toggle(&mut self, p: usize) {
+        let sign = if self.points[p] { -1.0 } else { 1.0 };
+        self.points[p] = !self.points[p];
+
+        let (px, py) = (p % MASK_SIZE, p / MASK_SIZE);
+        for (q, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (q % MASK_SIZE + MASK_SIZE - px) % MASK_SIZE;
+            let dy = (q / MASK_SIZE + MASK_SIZE - py) % MASK_SIZE;
+            *energy += sign * self.kernel[dy * MASK_SIZE + dx];
+        }
+    }
+
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |a, b| a > b)
+    }
+
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |a, b| a < b)
+    }
+
+    fn extreme(&self, point: bool, better: impl Fn(FP, FP) -> bool) -> usize {
+        self.energy
+            .iter()
+            .enumerate()
+            .filter(|&(p, _)| self.points[p] == point)
+            .reduce(|best, candidate| {
+                if better(*candidate.1, *best.1) {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .expect("Point set shouldn't be empty or full.")
+            .0
+    }
+}
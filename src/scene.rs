@@ -0,0 +1,962 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{
+    de::{self, value::SeqAccessDeserializer, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use toml::Spanned;
+
+use crate::{
+    animation::Animated,
+    bvh::{BVHBuilder, BVHNode},
+    camera::{Camera, CameraSettings, View},
+    common::FP,
+    constant_medium::ConstantMedium,
+    environment::{ConstantEnvironment, Environment, GradientEnvironment, ImageEnvironment},
+    hittable::{Hittable, HittableList, Tagged, Transform},
+    material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal},
+    matrix::Mat4,
+    mesh,
+    quad::Quad,
+    sky::SkyEnvironment,
+    sphere::Sphere,
+    texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture},
+    vec3::{Color, Point3, Vec3},
+};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        err: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        err: toml::de::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, err } => write!(f, "{}: {}", path.display(), err),
+            SceneError::Parse { path, err } => write!(f, "{}: {}", path.display(), err),
+            SceneError::Invalid {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path.display(), line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraSettings,
+    /// Replaces the background color of the camera
+    environment: Option<Spanned<EnvironmentDesc>>,
+    #[serde(default)]
+    textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+}
+
+/// Either an inline color or the name of an entry in `[textures]`.
+enum TextureRef {
+    Color(Color),
+    Named(String),
+}
+
+impl<'de> Deserialize<'de> for TextureRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TextureRefVisitor;
+
+        impl<'de> Visitor<'de> for TextureRefVisitor {
+            type Value = TextureRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a color `[r, g, b]` or the name of a texture")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(TextureRef::Named(v.to_owned()))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Color::deserialize(SeqAccessDeserializer::new(seq)).map(TextureRef::Color)
+            }
+        }
+
+        deserializer.deserialize_any(TextureRefVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid(Color),
+    Checker {
+        scale: FP,
+        even: Spanned<TextureRef>,
+        odd: Spanned<TextureRef>,
+    },
+    Image {
+        path: Spanned<String>,
+    },
+    Noise {
+        scale: FP,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum EnvironmentDesc {
+    Constant(Color),
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    Image {
+        path: Spanned<String>,
+        /// Degrees around the Y axis
+        #[serde(default)]
+        rotation: FP,
+        #[serde(default = "default_environment_intensity")]
+        intensity: FP,
+    },
+    /// Daylight, angles in degrees
+    Sky {
+        sun_elevation: FP,
+        #[serde(default)]
+        sun_azimuth: FP,
+        #[serde(default = "default_sun_size")]
+        sun_size: FP,
+        #[serde(default = "default_turbidity")]
+        turbidity: FP,
+        #[serde(default = "default_environment_intensity")]
+        intensity: FP,
+    },
+}
+
+fn default_environment_intensity() -> FP {
+    1.0
+}
+
+fn default_sun_size() -> FP {
+    0.53
+}
+
+fn default_turbidity() -> FP {
+    3.0
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: Spanned<TextureRef>,
+    },
+    Metal {
+        albedo: Color,
+        #[serde(default)]
+        fuzz: FP,
+    },
+    Dielectric {
+        ir: FP,
+    },
+    DiffuseLight {
+        emit: Spanned<TextureRef>,
+        #[serde(default = "default_intensity")]
+        intensity: Animated<FP>,
+    },
+    Isotropic {
+        albedo: Spanned<TextureRef>,
+    },
+}
+
+fn default_intensity() -> Animated<FP> {
+    Animated::Static(1.0)
+}
+
+impl MaterialDesc {
+    fn is_keyframed(&self) -> bool {
+        matches!(self, MaterialDesc::DiffuseLight { intensity, .. } if intensity.is_keyframed())
+    }
+}
+
+/// Transforms of an object, applied in the order they're listed.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate(Animated<Vec3>),
+    RotateX(Animated<FP>),
+    RotateY(Animated<FP>),
+    RotateZ(Animated<FP>),
+    Rotate {
+        axis: Animated<Vec3>,
+        angle: Animated<FP>,
+    },
+    Scale(ScaleDesc),
+    /// Rows of an affine matrix
+    Matrix([[FP; 4]; 4]),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(Animated<FP>),
+    PerAxis(Animated<Vec3>),
+}
+
+impl TransformDesc {
+    fn matrix(&self, time: FP) -> Mat4 {
+        match self {
+            TransformDesc::Translate(offset) => Mat4::translation(offset.value_at(time)),
+            TransformDesc::RotateX(angle) => Mat4::rotation(Vec3::RIGHT, angle.value_at(time)),
+            TransformDesc::RotateY(angle) => Mat4::rotation(Vec3::UP, angle.value_at(time)),
+            TransformDesc::RotateZ(angle) => {
+                Mat4::rotation(Vec3::new(0.0, 0.0, 1.0), angle.value_at(time))
+            }
+            TransformDesc::Rotate { axis, angle } => {
+                Mat4::rotation(axis.value_at(time), angle.value_at(time))
+            }
+            TransformDesc::Scale(ScaleDesc::Uniform(scale)) => {
+                Mat4::scaling(Vec3::splat(scale.value_at(time)))
+            }
+            TransformDesc::Scale(ScaleDesc::PerAxis(scale)) => Mat4::scaling(scale.value_at(time)),
+            TransformDesc::Matrix(rows) => Mat4::new(*rows),
+        }
+    }
+
+    fn is_keyframed(&self) -> bool {
+        match self {
+            TransformDesc::Translate(offset) => offset.is_keyframed(),
+            TransformDesc::RotateX(angle)
+            | TransformDesc::RotateY(angle)
+            | TransformDesc::RotateZ(angle) => angle.is_keyframed(),
+            TransformDesc::Rotate { axis, angle } => axis.is_keyframed() || angle.is_keyframed(),
+            TransformDesc::Scale(ScaleDesc::Uniform(scale)) => scale.is_keyframed(),
+            TransformDesc::Scale(ScaleDesc::PerAxis(scale)) => scale.is_keyframed(),
+            TransformDesc::Matrix(_) => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: Point3,
+        radius: FP,
+        material: Spanned<String>,
+        target: Option<Point3>,
+        #[serde(default)]
+        transform: Vec<Spanned<TransformDesc>>,
+        end_transform: Option<Vec<Spanned<TransformDesc>>>,
+    },
+    Quad {
+        q: Point3,
+        u: Vec3,
+        v: Vec3,
+        material: Spanned<String>,
+        #[serde(default)]
+        transform: Vec<Spanned<TransformDesc>>,
+        end_transform: Option<Vec<Spanned<TransformDesc>>>,
+    },
+    Cube {
+        a: Point3,
+        b: Point3,
+        material: Spanned<String>,
+        #[serde(default)]
+        transform: Vec<Spanned<TransformDesc>>,
+        end_transform: Option<Vec<Spanned<TransformDesc>>>,
+    },
+    Mesh {
+        path: Spanned<String>,
+        material: Option<Spanned<String>>,
+        #[serde(default)]
+        transform: Vec<Spanned<TransformDesc>>,
+        end_transform: Option<Vec<Spanned<TransformDesc>>>,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: FP,
+        albedo: Spanned<TextureRef>,
+        #[serde(default)]
+        transform: Vec<Spanned<TransformDesc>>,
+        end_transform: Option<Vec<Spanned<TransformDesc>>>,
+    },
+    Group {
+        objects: Vec<ObjectDesc>,
+        #[serde(default)]
+        bvh: bool,
+        #[serde(default)]
+        transform: Vec<Spanned<TransformDesc>>,
+        end_transform: Option<Vec<Spanned<TransformDesc>>>,
+    },
+}
+
+impl ObjectDesc {
+    fn transform(&self) -> &[Spanned<TransformDesc>] {
+        match self {
+            ObjectDesc::Sphere { transform, .. }
+            | ObjectDesc::Quad { transform, .. }
+            | ObjectDesc::Cube { transform, .. }
+            | ObjectDesc::Mesh { transform, .. }
+            | ObjectDesc::ConstantMedium { transform, .. }
+            | ObjectDesc::Group { transform, .. } => transform,
+        }
+    }
+
+    /// Where the object moves to while the shutter is open, in place of `transform`.
+    fn end_transform(&self) -> Option<&[Spanned<TransformDesc>]> {
+        match self {
+            ObjectDesc::Sphere { end_transform, .. }
+            | ObjectDesc::Quad { end_transform, .. }
+            | ObjectDesc::Cube { end_transform, .. }
+            | ObjectDesc::Mesh { end_transform, .. }
+            | ObjectDesc::ConstantMedium { end_transform, .. }
+            | ObjectDesc::Group { end_transform, .. } => end_transform.as_deref(),
+        }
+    }
+
+    /// Whether the object, or a material it uses, changes over time.
+    fn is_keyframed(&self, materials: &HashMap<String, MaterialDesc>) -> bool {
+        let material_keyframed = |name: &Spanned<String>| {
+            materials
+                .get(name.get_ref())
+                .is_some_and(MaterialDesc::is_keyframed)
+        };
+        let mut transforms = self
+            .transform()
+            .iter()
+            .chain(self.end_transform().unwrap_or(&[]));
+        if transforms.any(|t| t.get_ref().is_keyframed()) {
+            return true;
+        }
+        match self {
+            ObjectDesc::Sphere { material, .. }
+            | ObjectDesc::Quad { material, .. }
+            | ObjectDesc::Cube { material, .. } => material_keyframed(material),
+            ObjectDesc::Mesh { material, .. } => material.as_ref().is_some_and(material_keyframed),
+            ObjectDesc::ConstantMedium { boundary, .. } => boundary.is_keyframed(materials),
+            ObjectDesc::Group { objects, .. } => {
+                objects.iter().any(|object| object.is_keyframed(materials))
+            }
+        }
+    }
+}
+
+pub struct Scene {
+    pub world: HittableList,
+    pub camera: Camera,
+    /// The scene file and every asset read while building it
+    pub files: Vec<PathBuf>,
+}
+
+/// Builds the scene at `path` as it is at the start of its animation. Groups and meshes in it
+/// get their BVHs from `bvh`.
+pub fn load(path: &str, bvh: BVHBuilder) -> Result<Scene, SceneError> {
+    let path = Path::new(path);
+    let (source, scene) = read(path)?;
+    let mut cache = SceneCache::new(path);
+    let mut builder = SceneBuilder::new(path, &source, &scene, &mut cache, bvh, 0.0, 0.0);
+
+    let mut world = HittableList::default();
+    for object in &scene.objects {
+        world.add(builder.object(object)?);
+    }
+    let world = world.with_ids();
+    let mut camera = camera_at(&scene.camera, 0.0, 0.0);
+    if let Some(environment) = &scene.environment {
+        camera.environment = builder.environment(environment)?;
+    }
+
+    Ok(Scene {
+        world,
+        camera,
+        files: cache.files,
+    })
+}
+
+fn read(path: &Path) -> Result<(String, SceneFile), SceneError> {
+    let source = fs::read_to_string(path).map_err(|err| SceneError::Io {
+        path: path.to_owned(),
+        err,
+    })?;
+    let scene = toml::from_str(&source).map_err(|err| SceneError::Parse {
+        path: path.to_owned(),
+        err,
+    })?;
+    Ok((source, scene))
+}
+
+fn camera_at(settings: &CameraSettings, time: FP, duration: FP) -> Camera {
+    let mut camera = Camera::new(settings.clone());
+    let view = camera.view();
+    camera.set_view(settings.animation.view_at(view, time));
+    if duration > 0.0 {
+        camera.set_end_view(settings.animation.view_at(view, time + duration));
+    } else {
+        camera.set_end_view(View {
+            look_from: settings.end_look_from.unwrap_or(view.look_from),
+            look_at: settings.end_look_at.unwrap_or(view.look_at),
+            ..view
+        });
+    }
+    camera
+}
+
+/// A scene built at one point in time, ready to render.
+pub struct Frame {
+    pub world: Arc<dyn Hittable>,
+    pub lights: Arc<HittableList>,
+    pub camera: Camera,
+}
+
+/// Objects without keyframes, built once for all frames.
+struct StaticObjects {
+    bvh: Option<Arc<dyn Hittable>>,
+    lights: HittableList,
+}
+
+/// A scene file kept around to build it at any point in time. Only the objects with
+/// keyframes get rebuilt for every frame, the others share one BVH.
+pub struct SceneAnimation {
+    path: PathBuf,
+    source: String,
+    scene: SceneFile,
+    cache: SceneCache,
+    bvh: BVHBuilder,
+    static_objects: Option<StaticObjects>,
+}
+
+impl SceneAnimation {
+    pub fn load(path: &str, bvh: BVHBuilder) -> Result<Self, SceneError> {
+        let path = Path::new(path);
+        let (source, scene) = read(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            source,
+            scene,
+            cache: SceneCache::new(path),
+            bvh,
+            static_objects: None,
+        })
+    }
+
+    /// The scene file and every asset read for the frames built so far.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.cache.files
+    }
+
+    /// Builds the scene `time` seconds into the animation, moving over the next `duration`
+    /// seconds while the shutter is open.
+    pub fn frame(&mut self, time: FP, duration: FP) -> Result<Frame, SceneError> {
+        let materials = &self.scene.materials;
+        let mut builder = SceneBuilder::new(
+            &self.path,
+            &self.source,
+            &self.scene,
+            &mut self.cache,
+            self.bvh,
+            time,
+            duration,
+        );
+
+        if self.static_objects.is_none() {
+            let mut objects = HittableList::default();
+            for (id, object) in (1..).zip(&self.scene.objects) {
+                if !object.is_keyframed(materials) {
+                    objects.add(Arc::new(Tagged::new(builder.object(object)?, id)));
+                }
+            }
+            let lights = objects.lights();
+            let bvh = (!objects.is_empty()).then(|| {
+                Arc::new(BVHNode::new_with_builder(&mut objects, self.bvh)) as Arc<dyn Hittable>
+            });
+            self.static_objects = Some(StaticObjects { bvh, lights });
+        }
+
+        let mut objects = HittableList::default();
+        for (id, object) in (1..).zip(&self.scene.objects) {
+            if object.is_keyframed(materials) {
+                objects.add(Arc::new(Tagged::new(builder.object(object)?, id)));
+            }
+        }
+
+        let static_objects = self.static_objects.as_ref().unwrap();
+        let mut lights = HittableList::default();
+        for light in static_objects
+            .lights
+            .objects
+            .iter()
+            .chain(&objects.lights().objects)
+        {
+            lights.add(Arc::clone(light));
+        }
+        let mut world = HittableList::default();
+        if let Some(bvh) = &static_objects.bvh {
+            world.add(Arc::clone(bvh));
+        }
+        if !objects.is_empty() {
+            world.add(Arc::new(BVHNode::new_with_builder(&mut objects, self.bvh)));
+        }
+        let mut camera = camera_at(&self.scene.camera, time, duration);
+        if let Some(environment) = &self.scene.environment {
+            camera.environment = builder.environment(environment)?;
+        }
+
+        Ok(Frame {
+            world: Arc::new(world),
+            lights: Arc::new(lights),
+            camera,
+        })
+    }
+}
+
+/// What building a scene loads once and shares between frames.
+struct SceneCache {
+    textures: HashMap<String, Arc<dyn Texture>>,
+    /// Materials without keyframes
+    materials: HashMap<String, Arc<dyn Material>>,
+    /// Loaded meshes by path and default material, shared by all their instances
+    meshes: HashMap<(PathBuf, Option<String>), Arc<dyn Hittable>>,
+    environment: Option<Arc<dyn Environment>>,
+    /// The scene file and every asset read so far
+    files: Vec<PathBuf>,
+}
+
+impl SceneCache {
+    fn new(path: &Path) -> Self {
+        Self {
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            meshes: HashMap::new(),
+            environment: None,
+            files: vec![path.to_owned()],
+        }
+    }
+}
+
+struct SceneBuilder<'a> {
+    path: &'a Path,
+    source: &'a str,
+    texture_descs: &'a HashMap<String, TextureDesc>,
+    material_descs: &'a HashMap<String, MaterialDesc>,
+    cache: &'a mut SceneCache,
+    resolving: Vec<String>,
+    bvh: BVHBuilder,
+    /// Seconds into the animation keyframed values are taken at
+    time: FP,
+    /// Seconds the shutter time covers, keyframed transforms move over it
+    duration: FP,
+}
+
+impl<'a> SceneBuilder<'a> {
+    fn new(
+        path: &'a Path,
+        source: &'a str,
+        scene: &'a SceneFile,
+        cache: &'a mut SceneCache,
+        bvh: BVHBuilder,
+        time: FP,
+        duration: FP,
+    ) -> Self {
+        Self {
+            path,
+            source,
+            texture_descs: &scene.textures,
+            material_descs: &scene.materials,
+            cache,
+            resolving: Vec::new(),
+            bvh,
+            time,
+            duration,
+        }
+    }
+
+    fn error(&self, span: Range<usize>, message: String) -> SceneError {
+        let before = &self.source[..span.start];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        SceneError::Invalid {
+            path: self.path.to_owned(),
+            line,
+            column,
+            message,
+        }
+    }
+
+    fn texture(&mut self, texture: &Spanned<TextureRef>) -> Result<Arc<dyn Texture>, SceneError> {
+        let name = match texture.get_ref() {
+            TextureRef::Color(color) => return Ok(Arc::new(SolidColor::from(*color))),
+            TextureRef::Named(name) => name,
+        };
+
+        if let Some(texture) = self.cache.textures.get(name) {
+            return Ok(Arc::clone(texture));
+        }
+        if self.resolving.contains(name) {
+            return Err(self.error(
+                texture.span(),
+                format!("texture `{}` references itself", name),
+            ));
+        }
+        let descs = self.texture_descs;
+        let Some(desc) = descs.get(name) else {
+            return Err(self.error(texture.span(), format!("unknown texture `{}`", name)));
+        };
+
+        self.resolving.push(name.clone());
+        let built: Arc<dyn Texture> = match desc {
+            TextureDesc::Solid(color) => Arc::new(SolidColor::from(*color)),
+            TextureDesc::Checker { scale, even, odd } => Arc::new(CheckerTexture::new(
+                *scale,
+                self.texture(even)?,
+                self.texture(odd)?,
+            )),
+            TextureDesc::Image { path } => {
+                let full_path = self.relative_path(path.get_ref());
+                self.cache.files.push(full_path.clone());
+                let image = ImageTexture::open(&full_path.to_string_lossy()).map_err(|err| {
+                    self.error(path.span(), format!("{}: {}", path.get_ref(), err))
+                })?;
+                Arc::new(image)
+            }
+            TextureDesc::Noise { scale } => Arc::new(NoiseTexture::new(*scale)),
+        };
+        self.resolving.pop();
+
+        self.cache.textures.insert(name.clone(), Arc::clone(&built));
+        Ok(built)
+    }
+
+    fn environment(
+        &mut self,
+        desc: &Spanned<EnvironmentDesc>,
+    ) -> Result<Arc<dyn Environment>, SceneError> {
+        if let Some(environment) = &self.cache.environment {
+            return Ok(Arc::clone(environment));
+        }
+
+        let built: Arc<dyn Environment> = match desc.get_ref() {
+            EnvironmentDesc::Constant(color) => Arc::new(ConstantEnvironment::new(*color)),
+            EnvironmentDesc::Gradient { bottom, top } => {
+                Arc::new(GradientEnvironment::new(*bottom, *top))
+            }
+            EnvironmentDesc::Image {
+                path,
+                rotation,
+                intensity,
+            } => {
+                let full_path = self.relative_path(path.get_ref());
+                self.cache.files.push(full_path.clone());
+                let image =
+                    ImageEnvironment::open(&full_path.to_string_lossy(), *rotation, *intensity)
+                        .map_err(|err| {
+                            self.error(path.span(), format!("{}: {}", path.get_ref(), err))
+                        })?;
+                Arc::new(image)
+            }
+            EnvironmentDesc::Sky {
+                sun_elevation,
+                sun_azimuth,
+                sun_size,
+                turbidity,
+                intensity,
+            } => {
+                // Where the Preetham model holds
+                let message = if !(0.0..=90.0).contains(sun_elevation) {
+                    Some("the sun has to be 0 to 90 degrees above the horizon")
+                } else if !(1.7..=10.0).contains(turbidity) {
+                    Some("turbidity has to be between 1.7 and 10")
+                } else if !(*sun_size > 0.0 && *sun_size < 180.0) {
+                    Some("the sun has to be more than 0 and less than 180 degrees across")
+                } else {
+                    None
+                };
+                if let Some(message) = message {
+                    return Err(self.error(desc.span(), message.to_owned()));
+                }
+                Arc::new(SkyEnvironment::new(
+                    *sun_elevation,
+                    *sun_azimuth,
+                    *sun_size,
+                    *turbidity,
+                    *intensity,
+                ))
+            }
+        };
+
+        self.cache.environment = Some(Arc::clone(&built));
+        Ok(built)
+    }
+
+    fn material(&mut self, name: &Spanned<String>) -> Result<Arc<dyn Material>, SceneError> {
+        if let Some(material) = self.cache.materials.get(name.get_ref()) {
+            return Ok(Arc::clone(material));
+        }
+        let descs = self.material_descs;
+        let Some(desc) = descs.get(name.get_ref()) else {
+            return Err(self.error(
+                name.span(),
+                format!("unknown material `{}`", name.get_ref()),
+            ));
+        };
+
+        let built: Arc<dyn Material> = match desc {
+            MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian::new(self.texture(albedo)?)),
+            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(*albedo, *fuzz)),
+            MaterialDesc::Dielectric { ir } => Arc::new(Dielectric::new(*ir)),
+            MaterialDesc::DiffuseLight { emit, intensity } => Arc::new(
+                DiffuseLight::new(self.texture(emit)?)
+                    .with_intensity(intensity.value_at(self.time)),
+            ),
+            MaterialDesc::Isotropic { albedo } => Arc::new(Isotropic::new(self.texture(albedo)?)),
+        };
+
+        if !desc.is_keyframed() {
+            self.cache
+                .materials
+                .insert(name.get_ref().clone(), Arc::clone(&built));
+        }
+        Ok(built)
+    }
+
+    fn object(&mut self, desc: &ObjectDesc) -> Result<Arc<dyn Hittable>, SceneError> {
+        let (object, transform): (Arc<dyn Hittable>, _) = match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+                target,
+                transform,
+                ..
+            } => {
+                let sphere = Sphere::new(*center, *radius, self.material(material)?);
+                let sphere = match target {
+                    Some(target) => sphere.with_target(*target),
+                    None => sphere,
+                };
+                (Arc::new(sphere), transform)
+            }
+            ObjectDesc::Quad {
+                q,
+                u,
+                v,
+                material,
+                transform,
+                ..
+            } => (
+                Arc::new(Quad::new(*q, *u, *v, self.material(material)?)),
+                transform,
+            ),
+            ObjectDesc::Cube {
+                a,
+                b,
+                material,
+                transform,
+                ..
+            } => (Quad::cube(a, b, self.material(material)?), transform),
+            ObjectDesc::Mesh {
+                path,
+                material,
+                transform,
+                ..
+            } => {
+                let full_path = self.relative_path(path.get_ref());
+                let key = (
+                    full_path.clone(),
+                    material.as_ref().map(|m| m.get_ref().clone()),
+                );
+                // Meshes with a keyframed default material get rebuilt like the material is
+                let keyframed = material.as_ref().is_some_and(|material| {
+                    self.material_descs
+                        .get(material.get_ref())
+                        .is_some_and(MaterialDesc::is_keyframed)
+                });
+                if let Some(mesh) = self.cache.meshes.get(&key).filter(|_| !keyframed) {
+                    (Arc::clone(mesh), transform)
+                } else {
+                    let default_material = match material {
+                        Some(material) => self.material(material)?,
+                        None => Arc::new(Lambertian::new(Arc::new(SolidColor::from(
+                            Color::splat(0.8),
+                        )))),
+                    };
+                    let mesh: Arc<dyn Hittable> = Arc::new(
+                        mesh::load_obj(
+                            &full_path.to_string_lossy(),
+                            default_material,
+                            self.bvh,
+                            &mut self.cache.files,
+                        )
+                        .map_err(|err| {
+                            self.error(path.span(), format!("{}: {}", path.get_ref(), err))
+                        })?,
+                    );
+                    if !keyframed {
+                        self.cache.meshes.insert(key, Arc::clone(&mesh));
+                    }
+                    (mesh, transform)
+                }
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+                transform,
+                ..
+            } => (
+                Arc::new(ConstantMedium::new(
+                    self.object(boundary)?,
+                    *density,
+                    self.texture(albedo)?,
+                )),
+                transform,
+            ),
+            ObjectDesc::Group {
+                objects,
+                bvh,
+                transform,
+                ..
+            } => {
+                let mut list = HittableList::default();
+                for object in objects {
+                    list.add(self.object(object)?);
+                }
+                let group: Arc<dyn Hittable> = if *bvh && !list.is_empty() {
+                    Arc::new(BVHNode::new_with_builder(&mut list, self.bvh))
+                } else {
+                    Arc::new(list)
+                };
+                (group, transform)
+            }
+        };
+
+        let end_transform = desc.end_transform();
+        if transform.is_empty() && end_transform.is_none() {
+            return Ok(object);
+        }
+        let matrix = self.matrix(transform, self.time)?;
+        // Keyframed transforms move on to where they are when the frame ends
+        let end_matrix = self.matrix(
+            end_transform.unwrap_or(transform),
+            self.time + self.duration,
+        )?;
+
+        let instance = Transform::new(object, matrix)
+            .and_then(|instance| {
+                if end_matrix == matrix {
+                    Some(instance)
+                } else {
+                    instance.with_end(end_matrix)
+                }
+            })
+            .expect("Should've checked that the matrices can be inverted.");
+        Ok(Arc::new(instance))
+    }
+
+    /// Composes `transform` at `time` into one matrix.
+    fn matrix(&self, transform: &[Spanned<TransformDesc>], time: FP) -> Result<Mat4, SceneError> {
+        let mut matrix = Mat4::IDENTITY;
+        for desc in transform {
+            let m = desc.get_ref().matrix(time);
+            if !m.is_affine() {
+                return Err(self.error(
+                    desc.span(),
+                    "the bottom row of a matrix has to be [0, 0, 0, 1]".to_owned(),
+                ));
+            }
+            matrix = m * matrix;
+        }
+
+        match (matrix.inverse(), transform.first(), transform.last()) {
+            (None, Some(first), Some(last)) => Err(self.error(
+                first.span().start..last.span().end,
+                "transform can't be inverted, is something scaled by zero?".to_owned(),
+            )),
+            _ => Ok(matrix),
+        }
+    }
+
+    fn relative_path(&self, path: &str) -> PathBuf {
+        match self.path.parent() {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interval::Interval, ray::Ray, vec3::Point3};
+
+    /// Radiance `object` emits where a ray straight down -Z hits it.
+    fn emitted(object: &dyn Hittable) -> Color {
+        let ray = Ray::new(Point3::ZERO, Vec3::new(0.0, 0.0, -1.0));
+        let hit = object
+            .hit(&ray, &Interval::new(0.001, FP::INFINITY))
+            .expect("Should've hit the mesh");
+        hit.mat.emitted(hit.u, hit.v, &hit.p)
+    }
+
+    #[test]
+    fn mesh_with_keyframed_material_gets_rebuilt() {
+        let dir = std::env::temp_dir().join(format!("keyframed-mesh-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("Should've created the directory");
+        fs::write(
+            dir.join("glow.obj"),
+            "v -1 -1 -2\nv 1 -1 -2\nv 0 1 -2\nf 1 2 3\n",
+        )
+        .expect("Should've written the mesh");
+        fs::write(
+            dir.join("scene.toml"),
+            r#"
+                [materials]
+                glow = { diffuse_light = { emit = [1.0, 1.0, 1.0], intensity = { keyframes = [{ time = 0.0, value = 1.0 }, { time = 1.0, value = 3.0 }] } } }
+
+                [[objects]]
+                mesh = { path = "glow.obj", material = "glow" }
+            "#,
+        )
+        .expect("Should've written the scene");
+
+        let mut animation =
+            SceneAnimation::load(&dir.join("scene.toml").to_string_lossy(), BVHBuilder::Sah);
+        let frames = animation.as_mut().map(|animation| {
+            [0.0, 1.0].map(|time| {
+                animation
+                    .frame(time, 0.0)
+                    .expect("Should've built the frame")
+            })
+        });
+        fs::remove_dir_all(&dir).expect("Should've removed the directory");
+
+        let [start, end] = frames.expect("Should've loaded the scene");
+        assert_eq!(emitted(start.world.as_ref()), Color::splat(1.0));
+        assert_eq!(emitted(end.world.as_ref()), Color::splat(3.0));
+        assert_eq!(emitted(end.lights.as_ref()), Color::splat(3.0));
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    common::{degrees_to_radians, FP, PI},
+    environment::Environment,
+    onb::ONB,
+    vec3::{Color, Vec3},
+};
+
+/// Sky radiance is computed in kcd/m², this brings a sunlit white surface to around one.
+const UNITS: FP = 1.0 / 20.0;
+/// Luminance of the sun outside the atmosphere in kcd/m²
+const SUN_LUMINANCE: FP = 1.96e6;
+/// Wavelengths in micrometers the sunlight reaching the ground is attenuated at, one per channel
+const WAVELENGTHS: [FP; 3] = [0.68, 0.55, 0.44];
+/// Chance of next event estimation aiming at the sun disk rather than the rest of the sky
+const SUN_SAMPLING: FP = 0.5;
+
+/// Coefficients of the Perez sky luminance distribution.
+struct Perez([FP; 5]);
+
+impl Perez {
+    fn new(turbidity: FP, coefficients: [(FP, FP); 5]) -> Self {
+        Self(coefficients.map(|(a, b)| a * turbidity + b))
+    }
+
+    /// Relative brightness at an angle with cosine `cos_theta` from the zenith and `gamma`
+    /// radians from the sun.
+    fn value(&self, cos_theta: FP, gamma: FP) -> FP {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta.max(1e-4)).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Preetham daylight sky with a sun disk, black below the horizon where the scene is expected
+/// to have its ground.
+pub struct SkyEnvironment {
+    sun_direction: Vec3,
+    cos_sun_radius: FP,
+    sun_radiance: Color,
+    /// Luminance and chromaticity at the zenith, divided by the Perez value there
+    zenith: [FP; 3],
+    perez: [Perez; 3],
+}
+
+impl SkyEnvironment {
+    /// The sun is `elevation` degrees above the horizon, `azimuth` degrees from -Z towards +X,
+    /// and `size` degrees across. `turbidity` goes from 2 for a clear sky to 10 for haze.
+    pub fn new(elevation: FP, azimuth: FP, size: FP, turbidity: FP, intensity: FP) -> Self {
+        let (elevation, azimuth) = (degrees_to_radians(elevation), degrees_to_radians(azimuth));
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        let theta_s = PI / 2.0 - elevation;
+        let t = turbidity;
+
+        let perez = [
+            Perez::new(
+                t,
+                [
+                    (0.1787, -1.4630),
+                    (-0.3554, 0.4275),
+                    (-0.0227, 5.3251),
+                    (0.1206, -2.5771),
+                    (-0.0670, 0.3703),
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    (-0.0193, -0.2592),
+                    (-0.0665, 0.0008),
+                    (-0.0004, 0.2125),
+                    (-0.0641, -0.8989),
+                    (-0.0033, 0.0452),
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    (-0.0167, -0.2608),
+                    (-0.0950, 0.0092),
+                    (-0.0079, 0.2102),
+                    (-0.0441, -1.6537),
+                    (-0.0109, 0.0529),
+                ],
+            ),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let thetas = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+        let zenith_chromaticity = |m: [[FP; 4]; 3]| {
+            let row = |r: [FP; 4]| r.iter().zip(thetas).map(|(a, b)| a * b).sum::<FP>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = zenith_chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = zenith_chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let zenith = [zenith_luminance * UNITS * intensity, zenith_x, zenith_y];
+        let zenith = [0, 1, 2].map(|i| zenith[i] / perez[i].value(1.0, theta_s));
+
+        // Rayleigh and aerosol scattering out of the path through the atmosphere
+        let optical_mass =
+            1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).max(0.0).powf(-1.253));
+        let beta = 0.04608 * t - 0.04586;
+        let [r, g, b] = WAVELENGTHS.map(|lambda| {
+            let rayleigh = -0.008735 * lambda.powf(-4.08);
+            let aerosol = -beta * lambda.powf(-1.3);
+            ((rayleigh + aerosol) * optical_mass).exp()
+        });
+
+        Self {
+            sun_direction,
+            cos_sun_radius: degrees_to_radians(size / 2.0).cos(),
+            sun_radiance: Color::new(r, g, b) * SUN_LUMINANCE * UNITS * intensity,
+            zenith,
+            perez,
+        }
+    }
+
+    fn sky_radiance(&self, direction: &Vec3) -> Color {
+        let cos_theta = direction.y;
+        if cos_theta <= 0.0 {
+            return Color::ZERO;
+        }
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] =
+            [0, 1, 2].map(|i| self.zenith[i] * self.perez[i].value(cos_theta, gamma));
+        xyy_to_color(x, y, luminance)
+    }
+
+    fn in_sun(&self, direction: &Vec3) -> bool {
+        direction.dot(&self.sun_direction) >= self.cos_sun_radius
+    }
+
+    fn sun_pdf(&self) -> FP {
+        1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+    }
+}
+
+impl Environment for SkyEnvironment {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let direction = direction.normalize();
+        let mut radiance = self.sky_radiance(&direction);
+        if direction.y > 0.0 && self.in_sun(&direction) {
+            radiance += self.sun_radiance;
+        }
+        radiance
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> FP {
+        let direction = direction.normalize();
+        let mut pdf = 0.0;
+        if direction.y > 0.0 {
+            pdf += (1.0 - SUN_SAMPLING) / (2.0 * PI);
+        }
+        if self.in_sun(&direction) {
+            pdf += SUN_SAMPLING * self.sun_pdf();
+        }
+        pdf
+    }
+
+    fn sample(&self, (u1, u2): (FP, FP)) -> Vec3 {
+        if u1 < SUN_SAMPLING {
+            let sin_sun_radius = (1.0 - self.cos_sun_radius * self.cos_sun_radius).sqrt();
+            let local = Vec3::random_to_sphere(sin_sun_radius, 1.0, (u1 / SUN_SAMPLING, u2));
+            ONB::new(&self.sun_direction).local(&local)
+        } else {
+            // Uniform over the upper half
+            let u1 = (u1 - SUN_SAMPLING) / (1.0 - SUN_SAMPLING);
+            let d = Vec3::random_on_unit_sphere((u1, u2));
+            Vec3::new(d.x, d.y.abs(), d.z)
+        }
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+}
+
+/// Linear Rec. 709 color of the CIE chromaticity `(x, y)` with luminance `luminance`.
+fn xyy_to_color(x: FP, y: FP, luminance: FP) -> Color {
+    if y <= 0.0 {
+        return Color::ZERO;
+    }
+    let (cx, cz) = (x / y * luminance, (1.0 - x - y) / y * luminance);
+    let cy = luminance;
+    Color::new(
+        (3.2406 * cx - 1.5372 * cy - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * cy + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * cy + 1.0570 * cz).max(0.0),
+    )
+}
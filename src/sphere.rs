@@ -6,6 +6,7 @@ use crate::{
     hittable::{HitRecord, Hittable},
     interval::Interval,
     material::Material,
+    onb::ONB,
     ray::Ray,
     vec3::{Point3, Vec3},
 };
@@ -91,4 +92,38 @@ impl Hittable for Sphere {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> FP {
+        if self
+            .hit(
+                &Ray::new(*origin, *direction),
+                &Interval::new(0.001, FP::INFINITY),
+            )
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center - *origin).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return 0.0;
+        }
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Point3, u: (FP, FP)) -> Vec3 {
+        let direction = self.center - *origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::random_on_unit_sphere(u);
+        }
+        ONB::new(&direction).local(&Vec3::random_to_sphere(self.radius, distance_squared, u))
+    }
 }
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use image::{DynamicImage, GenericImageView};
+use image::{DynamicImage, GenericImageView, ImageError};
 
 use crate::{
     color::rgb_to_color,
@@ -74,9 +74,12 @@ pub struct ImageTexture {
 }
 impl ImageTexture {
     pub fn new(path: &str) -> Self {
-        Self {
-            image: image::io::Reader::open(path).unwrap().decode().unwrap(),
-        }
+        Self::open(path).unwrap()
+    }
+    pub fn open(path: &str) -> Result<Self, ImageError> {
+        Ok(Self {
+            image: image::io::Reader::open(path)?.decode()?,
+        })
     }
 }
 impl Texture for ImageTexture {
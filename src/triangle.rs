@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    common::FP,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    vec3::{Point3, Vec3},
+};
+
+pub struct Triangle {
+    p0: Point3,
+    e1: Vec3,
+    e2: Vec3,
+    normal: Vec3,
+    normals: Option<[Vec3; 3]>,
+    area: FP,
+    uvs: [(FP, FP); 3],
+    mat: Arc<dyn Material>,
+    bbox: AABB,
+}
+
+impl Triangle {
+    pub fn new(p0: Point3, p1: Point3, p2: Point3, mat: Arc<dyn Material>) -> Self {
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let bbox =
+            AABB::new_from_aabbs(AABB::new_from_points(p0, p1), AABB::new_from_points(p0, p2))
+                .pad();
+
+        let n = e1.cross(&e2);
+
+        Self {
+            p0,
+            e1,
+            e2,
+            normal: n.normalize(),
+            normals: None,
+            area: n.length() / 2.0,
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            mat,
+            bbox,
+        }
+    }
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Self {
+        self.normals = Some(normals.map(|n| n.normalize()));
+        self
+    }
+    pub fn with_uvs(mut self, uvs: [(FP, FP); 3]) -> Self {
+        self.uvs = uvs;
+        self
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        // Möller-Trumbore
+        let pvec = r.direction.cross(&self.e2);
+        let det = self.e1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin - self.p0;
+        let beta = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let qvec = tvec.cross(&self.e1);
+        let gamma = r.direction.dot(&qvec) * inv_det;
+        if gamma < 0.0 || beta + gamma > 1.0 {
+            return None;
+        }
+
+        let t = self.e2.dot(&qvec) * inv_det;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let alpha = 1.0 - beta - gamma;
+        let [uv0, uv1, uv2] = self.uvs;
+        let u = alpha * uv0.0 + beta * uv1.0 + gamma * uv2.0;
+        let v = alpha * uv0.1 + beta * uv1.1 + gamma * uv2.1;
+
+        let mut hit = HitRecord::new(r.at(t), self.mat.as_ref(), t, r, self.normal).with_uvs(u, v);
+
+        if let Some([n0, n1, n2]) = self.normals {
+            let shading_normal = (alpha * n0 + beta * n1 + gamma * n2).normalize();
+            hit.normal = if hit.front_face {
+                shading_normal
+            } else {
+                -shading_normal
+            };
+        }
+
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> FP {
+        if let Some(hit) = self.hit(
+            &Ray::new(*origin, *direction),
+            &Interval::new(0.001, FP::INFINITY),
+        ) {
+            let distance_squared = hit.t * hit.t * direction.length_squared();
+            // The geometric normal, the shading one doesn't describe the area
+            let cosine = (direction.dot(&self.normal) / direction.length()).abs();
+
+            distance_squared / (cosine * self.area)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, origin: &Point3, (u1, u2): (FP, FP)) -> Vec3 {
+        // Folding the square onto the triangle with a square root keeps the points uniform
+        let s = u1.sqrt();
+        let p = self.p0 + s * (1.0 - u2) * self.e1 + s * u2 * self.e2;
+        p - *origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::HittableList, material::DiffuseLight, quad::Quad, texture::SolidColor};
+
+    #[test]
+    fn triangle_pair_samples_like_a_quad() {
+        let light: Arc<dyn Material> =
+            Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(1.0, 1.0, 1.0))));
+        let [p0, p1, p2, p3] = [
+            Point3::ZERO,
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 1.0),
+            Point3::new(0.0, 0.0, 1.0),
+        ];
+        let mut triangles = HittableList::default();
+        triangles.add(Arc::new(Triangle::new(p0, p1, p2, Arc::clone(&light))));
+        triangles.add(Arc::new(Triangle::new(p0, p2, p3, Arc::clone(&light))));
+        let quad = Quad::new(p0, p1 - p0, p3 - p0, light);
+
+        assert_eq!(triangles.lights().objects.len(), 2);
+        let origin = Point3::new(0.5, 3.0, -1.0);
+        for i in 0..16 {
+            let u = ((i as FP + 0.5) / 16.0, ((i * 5 % 16) as FP + 0.5) / 16.0);
+            let direction = triangles.random(&origin, u);
+            let (a, b) = (
+                triangles.pdf_value(&origin, &direction),
+                quad.pdf_value(&origin, &direction),
+            );
+            assert!(b > 0.0, "sampled a direction missing the quad");
+            assert!((a - b).abs() < 1e-9 * b, "{a} != {b}");
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use crate::common::FP;
+use crate::common::{self, FP, PI};
 use core::fmt;
-use rand::Rng;
+use serde::Deserialize;
 use std::ops::{
     Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
 };
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(from = "[FP; 3]")]
 pub struct Vec3 {
     pub x: FP,
     pub y: FP,
@@ -40,14 +41,14 @@ impl Vec3 {
 
     #[inline]
     pub fn random() -> Self {
-        Self::new(rand::random(), rand::random(), rand::random())
+        Self::new(common::random(), common::random(), common::random())
     }
     #[inline]
     pub fn random_range(min: FP, max: FP) -> Self {
         Self::new(
-            rand::thread_rng().gen_range(min..max),
-            rand::thread_rng().gen_range(min..max),
-            rand::thread_rng().gen_range(min..max),
+            common::random_range(min..max),
+            common::random_range(min..max),
+            common::random_range(min..max),
         )
     }
     #[inline]
@@ -73,18 +74,50 @@ impl Vec3 {
         }
     }
 
+    /// Uniform point on the unit sphere from the sample `(r1, r2)`.
     #[inline]
-    pub fn random_in_unit_disk() -> Self {
-        loop {
-            let p = Self::new(
-                rand::thread_rng().gen_range(-1.0..1.0),
-                rand::thread_rng().gen_range(-1.0..1.0),
-                0.0,
-            );
-            if p.length_squared() < 1.0 {
-                return p;
-            }
-        }
+    pub fn random_on_unit_sphere((r1, r2): (FP, FP)) -> Self {
+        let z = 1.0 - 2.0 * r1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * r2;
+
+        Self::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// Uniform point inside the unit sphere from the sample `(r1, r2, r3)`.
+    #[inline]
+    pub fn random_in_unit_ball((r1, r2, r3): (FP, FP, FP)) -> Self {
+        Self::random_on_unit_sphere((r1, r2)) * r3.cbrt()
+    }
+
+    /// Uniform point on the unit disk in the XY plane from the sample `(r1, r2)`.
+    #[inline]
+    pub fn random_in_unit_disk((r1, r2): (FP, FP)) -> Self {
+        let r = r1.sqrt();
+        let phi = 2.0 * PI * r2;
+
+        Self::new(r * phi.cos(), r * phi.sin(), 0.0)
+    }
+
+    #[inline]
+    pub fn random_cosine_direction((r1, r2): (FP, FP)) -> Self {
+        let phi = 2.0 * PI * r1;
+        let x = phi.cos() * r2.sqrt();
+        let y = phi.sin() * r2.sqrt();
+        let z = (1.0 - r2).sqrt();
+
+        Self::new(x, y, z)
+    }
+
+    #[inline]
+    pub fn random_to_sphere(radius: FP, distance_squared: FP, (r1, r2): (FP, FP)) -> Self {
+        let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+
+        let phi = 2.0 * PI * r1;
+        let x = phi.cos() * (1.0 - z * z).sqrt();
+        let y = phi.sin() * (1.0 - z * z).sqrt();
+
+        Self::new(x, y, z)
     }
 
     #[inline]
@@ -139,6 +172,13 @@ impl Vec3 {
     }
 }
 
+impl From<[FP; 3]> for Vec3 {
+    #[inline]
+    fn from([x, y, z]: [FP; 3]) -> Self {
+        Self::new(x, y, z)
+    }
+}
+
 impl Default for Vec3 {
     #[inline(always)]
     fn default() -> Self {
@@ -232,6 +272,15 @@ impl Mul<FP> for Vec3 {
     }
 }
 
+impl MulAssign for Vec3 {
+    #[inline]
+    fn mul_assign(&mut self, rhs: Self) {
+        self.x *= rhs.x;
+        self.y *= rhs.y;
+        self.z *= rhs.z;
+    }
+}
+
 impl MulAssign<FP> for Vec3 {
     #[inline]
     fn mul_assign(&mut self, s: FP) {